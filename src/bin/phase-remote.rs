@@ -0,0 +1,88 @@
+//! Sends remote control commands to a running controller, and prints the replies.
+//! The commands are json, given as arguments or one per line on the standard input.
+//! With ```--follow``` the events sent by the controller are printed afterwards
+
+extern crate clap;
+extern crate phase;
+extern crate serde_json;
+
+use clap::{App, Arg};
+use std::io::{self, BufRead};
+use std::process;
+
+use phase::remote::{Client, Command, Reply};
+
+/// Send one command, printing the reply. Returns false if the command failed
+fn send(client: &mut Client, text: &str) -> Result<bool, String> {
+    let command = serde_json::from_str::<Command>(text)
+        .map_err(|e| format!("couldn't read the command {}: {}", text, e))?;
+    let reply = client
+        .send(&command)
+        .map_err(|e| format!("couldn't send the command: {}", e))?;
+    println!("{}", serde_json::to_string(&reply).map_err(|e| e.to_string())?);
+    Ok(!matches!(reply, Reply::Error { .. }))
+}
+
+fn run() -> Result<bool, String> {
+    let matches = App::new("phase-remote")
+        .about("Send remote control commands to a running controller")
+        .arg(
+            Arg::with_name("address")
+                .long("address")
+                .takes_value(true)
+                .default_value("127.0.0.1:7878")
+                .help("the address the controller listens on"),
+        )
+        .arg(
+            Arg::with_name("follow")
+                .long("follow")
+                .help("after the commands, print the events sent by the controller until it stops"),
+        )
+        .arg(
+            Arg::with_name("commands")
+                .multiple(true)
+                .help("json commands, such as '{\"command\": \"render\"}'. Without any, they are read from the standard input"),
+        )
+        .get_matches();
+
+    let address = matches.value_of("address").unwrap();
+    let mut client =
+        Client::connect(address).map_err(|e| format!("couldn't connect to {}: {}", address, e))?;
+    let mut succeeded = true;
+    if let Some(commands) = matches.values_of("commands") {
+        for command in commands {
+            succeeded &= send(&mut client, command)?;
+        }
+    } else if !matches.is_present("follow") {
+        for line in io::stdin().lock().lines() {
+            let line = line.map_err(|e| e.to_string())?;
+            if !line.trim().is_empty() {
+                succeeded &= send(&mut client, &line)?;
+            }
+        }
+    }
+    if matches.is_present("follow") {
+        succeeded &= send(&mut client, r#"{"command": "subscribe"}"#)?;
+        loop {
+            match client.next_event() {
+                Ok(event) => {
+                    println!("{}", serde_json::to_string(&event).map_err(|e| e.to_string())?)
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+    Ok(succeeded)
+}
+
+fn main() {
+    match run() {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("phase-remote: {}", e);
+            process::exit(1);
+        }
+    }
+}
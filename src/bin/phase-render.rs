@@ -0,0 +1,140 @@
+//! Renders a saved container file into an image, without opening the GUI.
+//! The image format is chosen from the output file's extension (png, bmp, tiff, ...).
+//! With ```--phase``` the full precision phase is exported instead.
+//! With ```--sequence``` each frame of a sequence is written into the output directory
+
+extern crate clap;
+extern crate image;
+extern crate phase;
+extern crate serde_json;
+
+use clap::{App, Arg};
+use std::fs::File;
+use std::process;
+
+use phase::device::{Calibration, DeviceProfile};
+use phase::export::{self, Format};
+use phase::render;
+use phase::sequence::{self, Sequence};
+use phase::slm_data::SLMData;
+
+/// Parse a dimension given on the command line
+fn parse_size(value: Option<&str>, name: &str) -> Result<Option<usize>, String> {
+    value
+        .map(|v| v.parse::<usize>().map_err(|e| format!("invalid {}: {}", name, e)))
+        .transpose()
+}
+
+fn run() -> Result<(), String> {
+    let matches = App::new("phase-render")
+        .about("Render a saved container file into a phase mask image")
+        .arg(
+            Arg::with_name("input")
+                .help("the container json file written by the GUI")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .required(true)
+                .help("the image file to write, or the directory for the frames of a sequence"),
+        )
+        .arg(
+            Arg::with_name("device")
+                .long("device")
+                .takes_value(true)
+                .help("a device profile json file, replacing the device in the input file"),
+        )
+        .arg(
+            Arg::with_name("width")
+                .long("width")
+                .takes_value(true)
+                .help("override the width of the device"),
+        )
+        .arg(
+            Arg::with_name("height")
+                .long("height")
+                .takes_value(true)
+                .help("override the height of the device"),
+        )
+        .arg(Arg::with_name("phase").long("phase").help(
+            "export the phase in radians instead of the device's grey levels. \
+             The format is chosen from the extension: png or tiff (16 bit), \
+             npy (f64) or raw (f64, with a json sidecar)",
+        ))
+        .arg(
+            Arg::with_name("unwrapped")
+                .long("unwrapped")
+                .requires("phase")
+                .help("don't wrap the phase after the corrections are added"),
+        )
+        .arg(
+            Arg::with_name("sequence")
+                .long("sequence")
+                .takes_value(true)
+                .help(
+                    "a sequence json file, whose frames are rendered from the containers in the \
+                     input file. The frames are named by the values of the swept fields. \
+                     With --phase they are 16 bit pngs of the wrapped phase",
+                ),
+        )
+        .get_matches();
+
+    let input = matches.value_of("input").unwrap();
+    let output = matches.value_of("output").unwrap();
+    let width = parse_size(matches.value_of("width"), "width")?;
+    let height = parse_size(matches.value_of("height"), "height")?;
+
+    let file = File::open(input).map_err(|e| format!("couldn't open {}: {}", input, e))?;
+    let mut data = SLMData::from_reader(file)
+        .map_err(|e| format!("couldn't read containers from {}: {}", input, e))?;
+    if let Some(path) = matches.value_of("device") {
+        data.device = DeviceProfile::load(path)
+            .map_err(|e| format!("couldn't read device profile from {}: {}", path, e))?;
+    }
+    data.device.width = width.unwrap_or(data.device.width);
+    data.device.height = height.unwrap_or(data.device.height);
+    data.device.validate()?;
+
+    let calibration = Calibration::load(&data.device)
+        .map_err(|e| format!("couldn't load the device calibration: {}", e))?;
+    if let Some(path) = matches.value_of("sequence") {
+        let file = File::open(path).map_err(|e| format!("couldn't open {}: {}", path, e))?;
+        let sequence = Sequence::from_reader(file)
+            .map_err(|e| format!("couldn't read the sequence from {}: {}", path, e))?;
+        let frames = sequence.frames(&data.containers)?;
+        let format = if matches.is_present("phase") {
+            Some(Format::Png16)
+        } else {
+            None
+        };
+        return sequence::export_frames(output, &frames, &data, &calibration, format)
+            .map(|_| ())
+            .map_err(|e| format!("couldn't write the frames to {}: {}", output, e));
+    }
+    if matches.is_present("phase") {
+        let format = Format::from_path(output)
+            .ok_or_else(|| format!("can't export the phase to {}", output))?;
+        let wrapped = !matches.is_present("unwrapped");
+        return export::export(output, format, &data, &calibration, wrapped)
+            .map_err(|e| format!("couldn't write {}: {}", output, e));
+    }
+    let grey = render::render_device(&data, &calibration);
+    image::save_buffer(
+        output,
+        &grey,
+        data.device.width as u32,
+        data.device.height as u32,
+        image::ColorType::L8,
+    )
+    .map_err(|e| format!("couldn't write {}: {}", output, e))
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("phase-render: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,140 @@
+//! This module runs the SLM without the editor, for long unattended runs.
+//! Only the fullscreen output window is opened, and the patterns are changed over the
+//! remote control. Every command which changes the patterns renders them again.
+//! It runs until the remote control sends ```quit```, or the output window is closed
+
+use gtk::WidgetExt;
+use relm::{Component, Relm, Update, Widget};
+use std::net::TcpListener;
+use std::sync::mpsc;
+
+use self::DaemonMsg::*;
+
+use crate::gui::{grey_to_pixbuf, load_calibration, QUIT_DELAY};
+use crate::output_window::{OutputWindow, OutputWindowMsg};
+use phase::device::Calibration;
+use phase::remote::{self, Command, Events, Reply};
+use phase::render;
+use phase::slm_data::SLMData;
+
+/// The model for the daemon
+pub struct DaemonModel {
+    /// The patterns being displayed, changed by the remote control
+    data: SLMData,
+    /// The calibration loaded from the files the device profile refers to
+    calibration: Calibration,
+    /// The monitor the SLM is attached to
+    monitor: i32,
+    /// The remote control listener, which is served once the output window is open
+    listener: Option<TcpListener>,
+}
+
+/// The messages which the daemon accepts
+#[derive(Msg)]
+pub enum DaemonMsg {
+    // A command from the remote control, with the sender for its reply
+    Remote(Box<Command>, mpsc::Sender<Reply>),
+    Quit,
+}
+
+/// Displays the patterns on the SLM, and answers the remote control
+pub struct Daemon {
+    model: DaemonModel,
+    relm: Relm<Daemon>,
+    output_window: Component<OutputWindow>,
+    /// receives the commands from the remote control server
+    _remote_channel: relm::Channel<DaemonMsg>,
+}
+
+impl Daemon {
+    /// Render the patterns and show them on the SLM
+    fn render(&self) {
+        let device = &self.model.data.device;
+        let phase = render::render_device_phase(&self.model.data, &self.model.calibration);
+        let grey = render::device_grey(&self.model.data, &self.model.calibration, &phase);
+        let pixbuf = grey_to_pixbuf(&grey, device.width as i32, device.height as i32);
+        self.output_window
+            .stream()
+            .emit(OutputWindowMsg::SetImage(pixbuf));
+    }
+
+    /// Carry out a command from the remote control, rendering the patterns if they changed
+    fn handle_remote(&mut self, command: Command) -> Reply {
+        let reply = remote::apply(&command, &mut self.model.data);
+        if let Reply::Error { .. } = reply {
+            return reply;
+        }
+        match command {
+            Command::Quit => relm::timeout(self.relm.stream(), QUIT_DELAY, || Quit),
+            Command::GetData | Command::Subscribe => (),
+            Command::LoadFile { .. } => {
+                self.model.calibration = load_calibration(&self.model.data.device);
+                self.render();
+            }
+            _ => self.render(),
+        }
+        reply
+    }
+}
+
+impl Update for Daemon {
+    type Model = DaemonModel;
+    /// The patterns to display first, the monitor to display them on, and the listener for
+    /// the remote control, which is bound before the daemon starts so failures can be reported
+    type ModelParam = (SLMData, i32, TcpListener);
+    type Msg = DaemonMsg;
+
+    fn model(_: &Relm<Self>, (data, monitor, listener): Self::ModelParam) -> Self::Model {
+        DaemonModel {
+            calibration: load_calibration(&data.device),
+            data,
+            monitor,
+            listener: Some(listener),
+        }
+    }
+
+    fn update(&mut self, event: Self::Msg) {
+        match event {
+            Remote(command, sender) => {
+                let reply = self.handle_remote(*command);
+                let _ = sender.send(reply);
+            }
+            Quit => gtk::main_quit(),
+        }
+    }
+}
+
+impl Widget for Daemon {
+    type Root = gtk::Window;
+
+    fn root(&self) -> Self::Root {
+        self.output_window.widget().clone()
+    }
+
+    fn view(relm: &Relm<Self>, mut model: Self::Model) -> Self {
+        let output_window = relm::create_component::<OutputWindow>(());
+        output_window
+            .stream()
+            .emit(OutputWindowMsg::SetMonitor(model.monitor));
+        output_window.stream().emit(OutputWindowMsg::SetVisible(true));
+        // the output window only hides when it is closed, which is the end of the daemon
+        connect!(relm, output_window.widget(), connect_hide(_), Quit);
+
+        let stream = relm.stream().clone();
+        let (channel, sender) = relm::Channel::new(move |msg| stream.emit(msg));
+        if let Some(listener) = model.listener.take() {
+            remote::serve_on(listener, Events::default(), move |command, reply| {
+                let _ = sender.send(Remote(Box::new(command), reply));
+            });
+        }
+
+        let daemon = Daemon {
+            model,
+            relm: relm.clone(),
+            output_window,
+            _remote_channel: channel,
+        };
+        daemon.render();
+        daemon
+    }
+}
@@ -0,0 +1,236 @@
+//! This module describes the SLM device which the patterns are displayed on
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use crate::lut::Lut;
+use crate::phase_map::PhaseMap;
+use crate::render::wrap_phase;
+
+/// A lookup table which calibrates the device at one wavelength
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WavelengthLut {
+    /// The wavelength the table was measured at, in nm
+    pub wavelength: f64,
+    pub path: PathBuf,
+}
+
+/// The properties of an SLM which are needed to create patterns for it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeviceProfile {
+    pub name: String,
+    /// The number of pixels across the SLM
+    pub width: usize,
+    /// The number of pixels down the SLM
+    pub height: usize,
+    /// The distance between neighbouring pixels, in μm
+    pub pixel_pitch: f64,
+    /// The number of bits in each pixel's grey level
+    pub bit_depth: u32,
+    /// The grey level which gives a phase shift of 2π
+    pub two_pi_level: u32,
+    /// The wavelength the SLM is used at, in nm
+    pub wavelength: f64,
+    /// The lookup table file mapping phase to grey level.
+    /// Without a table, the phase is mapped linearly up to ```two_pi_level```
+    #[serde(default)]
+    pub lut: Option<PathBuf>,
+    /// Lookup tables for particular wavelengths, which are used instead of ```lut```
+    /// when one matches ```wavelength```
+    #[serde(default)]
+    pub wavelength_luts: Vec<WavelengthLut>,
+    /// The manufacturer's flatness correction image, which is added to every pattern
+    #[serde(default)]
+    pub correction_bitmap: Option<PathBuf>,
+    /// The grey level of the correction image which is a phase of 2π.
+    /// If it isn't given, the whole range of the image is 2π
+    #[serde(default)]
+    pub correction_two_pi_level: Option<f64>,
+}
+
+impl Default for DeviceProfile {
+    fn default() -> Self {
+        DeviceProfile {
+            name: String::from("Generic 1920x1080"),
+            width: 1920,
+            height: 1080,
+            pixel_pitch: 8.0,
+            bit_depth: 8,
+            two_pi_level: 256,
+            wavelength: 633.0,
+            lut: None,
+            wavelength_luts: vec![],
+            correction_bitmap: None,
+            correction_two_pi_level: None,
+        }
+    }
+}
+
+impl DeviceProfile {
+    /// Read a device profile from json, checking that it describes a usable device
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<DeviceProfile> {
+        let device: DeviceProfile = serde_json::de::from_reader(reader)?;
+        device
+            .validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(device)
+    }
+
+    /// Load a device profile from a json file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<DeviceProfile> {
+        DeviceProfile::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Check that the device has some pixels, and that its 2π level is one of its grey levels
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err(format!(
+                "the device is {}x{} pixels, but it must be at least 1x1",
+                self.width, self.height
+            ));
+        }
+        if self.bit_depth == 0 || self.bit_depth > 16 {
+            return Err(format!(
+                "the bit depth is {}, but it must be between 1 and 16",
+                self.bit_depth
+            ));
+        }
+        if self.two_pi_level == 0 || self.two_pi_level > self.max_level() + 1 {
+            return Err(format!(
+                "the 2π level is {}, but it must be between 1 and {} for a {} bit device",
+                self.two_pi_level,
+                self.max_level() + 1,
+                self.bit_depth
+            ));
+        }
+        Ok(())
+    }
+
+    /// The largest grey level the device accepts
+    pub fn max_level(&self) -> u32 {
+        (1u32 << self.bit_depth.min(16)) - 1
+    }
+
+    /// Convert a phase into the grey level which gives that phase on the device
+    pub fn phase_to_level(&self, phase: f64) -> u16 {
+        let level = (wrap_phase(phase) / (2.0 * PI) * f64::from(self.two_pi_level)) as u32;
+        level.min(self.max_level()) as u16
+    }
+
+    /// The lookup table file for the device's wavelength, if there is one.
+    /// A wavelength specific table is used if it is within 1nm of the device's wavelength
+    pub fn lut_path(&self) -> Option<&Path> {
+        self.wavelength_luts
+            .iter()
+            .find(|lut| (lut.wavelength - self.wavelength).abs() < 1.0)
+            .map(|lut| lut.path.as_path())
+            .or(self.lut.as_deref())
+    }
+
+    /// Convert a grey level of the device into an 8 bit grey level for the screen.
+    /// Devices with more than 8 bits only keep their most significant bits
+    pub fn level_to_grey(&self, level: u16) -> u8 {
+        (u32::from(level) >> self.bit_depth.saturating_sub(8)).min(255) as u8
+    }
+}
+
+/// The device profiles which are built in to the program
+pub fn presets() -> Vec<DeviceProfile> {
+    vec![
+        DeviceProfile::default(),
+        DeviceProfile {
+            name: String::from("1272x1024, 12.5μm"),
+            width: 1272,
+            height: 1024,
+            pixel_pitch: 12.5,
+            ..Default::default()
+        },
+        DeviceProfile {
+            name: String::from("1920x1152, 9.2μm"),
+            width: 1920,
+            height: 1152,
+            pixel_pitch: 9.2,
+            ..Default::default()
+        },
+        DeviceProfile {
+            name: String::from("4K 4160x2464, 3.74μm"),
+            width: 4160,
+            height: 2464,
+            pixel_pitch: 3.74,
+            ..Default::default()
+        },
+    ]
+}
+
+/// The calibration data for a device, which is loaded from the files its profile refers to
+#[derive(Clone, Debug, Default)]
+pub struct Calibration {
+    pub lut: Option<Lut>,
+    /// The flatness correction of the device, aligned with the top left of the screen
+    pub correction: Option<PhaseMap>,
+}
+
+impl Calibration {
+    /// Load the calibration files referred to by the device profile
+    pub fn load(device: &DeviceProfile) -> io::Result<Calibration> {
+        Ok(Calibration {
+            lut: device.lut_path().map(Lut::load).transpose()?,
+            correction: device
+                .correction_bitmap
+                .as_ref()
+                .map(|path| PhaseMap::load_image(path, device.correction_two_pi_level))
+                .transpose()?,
+        })
+    }
+
+    /// Convert a phase into the grey level of the device.
+    /// The lookup table is used if there is one, otherwise the phase is mapped linearly
+    pub fn phase_to_level(&self, device: &DeviceProfile, phase: f64) -> u16 {
+        match self.lut {
+            Some(ref lut) => lut.phase_to_level(phase).min(device.max_level() as u16),
+            None => device.phase_to_level(phase),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid() {
+        for preset in presets() {
+            assert_eq!(preset.validate(), Ok(()), "{}", preset.name);
+        }
+    }
+
+    #[test]
+    fn rejects_devices_without_pixels() {
+        let json = r#"{"name": "empty", "width": 0, "height": 1080, "pixel_pitch": 8.0,
+            "bit_depth": 8, "two_pi_level": 256, "wavelength": 633.0}"#;
+        let error = DeviceProfile::from_reader(json.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_two_pi_levels_outside_the_grey_levels() {
+        let device = DeviceProfile {
+            two_pi_level: 0,
+            ..Default::default()
+        };
+        assert!(device.validate().is_err());
+        let device = DeviceProfile {
+            two_pi_level: 257,
+            ..Default::default()
+        };
+        assert!(device.validate().is_err());
+        let device = DeviceProfile {
+            bit_depth: 10,
+            two_pi_level: 1024,
+            ..Default::default()
+        };
+        assert_eq!(device.validate(), Ok(()));
+    }
+}
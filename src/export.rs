@@ -0,0 +1,303 @@
+//! This module exports the rendered phase in full precision, to archive exactly what was
+//! displayed and to analyse it.
+//! Phases are written in radians, except in 16 bit images where 65536 is 2π
+use image::{ImageBuffer, Luma};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::device::{Calibration, DeviceProfile};
+use crate::npy;
+use crate::render::{self, wrap_phase};
+use crate::slm_data::SLMData;
+
+/// The formats the phase can be exported in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// A 16 bit greyscale png, where 65536 is 2π. The phase is always wrapped
+    Png16,
+    /// A 16 bit greyscale tiff, where 65536 is 2π. The phase is always wrapped
+    Tiff16,
+    /// A numpy array of f32 radians
+    NpyF32,
+    /// A numpy array of f64 radians
+    NpyF64,
+    /// Raw little endian f32 radians, with a json sidecar describing them
+    RawF32,
+    /// Raw little endian f64 radians, with a json sidecar describing them
+    RawF64,
+}
+
+impl Format {
+    /// The names of the formats, in the order they are offered in the GUI
+    pub const NAMES: &'static [&'static str] = &[
+        "16 bit png",
+        "16 bit tiff",
+        "npy f32",
+        "npy f64",
+        "raw f32",
+        "raw f64",
+    ];
+
+    /// The format with the given name
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "16 bit png" => Some(Format::Png16),
+            "16 bit tiff" => Some(Format::Tiff16),
+            "npy f32" => Some(Format::NpyF32),
+            "npy f64" => Some(Format::NpyF64),
+            "raw f32" => Some(Format::RawF32),
+            "raw f64" => Some(Format::RawF64),
+            _ => None,
+        }
+    }
+
+    /// The format for a file extension: png, tif or tiff, npy (f64), and raw or bin (f64)
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Format> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "png" => Some(Format::Png16),
+            "tif" | "tiff" => Some(Format::Tiff16),
+            "npy" => Some(Format::NpyF64),
+            "raw" | "bin" => Some(Format::RawF64),
+            _ => None,
+        }
+    }
+
+    /// The usual extension of files in the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png16 => "png",
+            Format::Tiff16 => "tiff",
+            Format::NpyF32 | Format::NpyF64 => "npy",
+            Format::RawF32 | Format::RawF64 => "raw",
+        }
+    }
+}
+
+/// The description of a raw export, written next to it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Sidecar {
+    pub width: usize,
+    pub height: usize,
+    /// The type of each value, ```f32``` or ```f64```
+    pub element: String,
+    pub byte_order: String,
+    /// The values are stored row by row
+    pub order: String,
+    /// The number of radians in one unit of the values
+    pub scale: f64,
+    /// Whether the phase has been wrapped into [0, 2π)
+    pub wrapped: bool,
+    pub device: String,
+    /// The size of the device's pixels, in μm
+    pub pixel_pitch: f64,
+    /// The wavelength the device was set up for, in nm
+    pub wavelength: f64,
+}
+
+/// The path of the json sidecar for a raw export, which is the path with ```.json``` added
+pub fn sidecar_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut sidecar = path.as_ref().as_os_str().to_owned();
+    sidecar.push(".json");
+    PathBuf::from(sidecar)
+}
+
+/// Write the wrapped phase as a 16 bit greyscale image, where 65536 is 2π
+pub(crate) fn write_image<P: AsRef<Path>>(
+    path: P,
+    phase: &[f64],
+    width: usize,
+    height: usize,
+) -> io::Result<()> {
+    let levels = phase
+        .iter()
+        .map(|&p| (wrap_phase(p) / (2.0 * PI) * 65536.0).min(65535.0) as u16)
+        .collect::<Vec<_>>();
+    let buffer = ImageBuffer::<Luma<u16>, _>::from_raw(width as u32, height as u32, levels)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "phase has the wrong size"))?;
+    buffer.save(path).map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// Write a ```width``` x ```height``` phase, stored row by row, to ```path``` in the format.
+/// The device is described in the sidecar of raw exports
+pub fn export_phase<P: AsRef<Path>>(
+    path: P,
+    format: Format,
+    phase: &[f64],
+    width: usize,
+    height: usize,
+    wrapped: bool,
+    device: &DeviceProfile,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    if phase.len() != width * height {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "phase has the wrong size",
+        ));
+    }
+    let wrapped_phase;
+    let phase = if wrapped {
+        wrapped_phase = phase.iter().cloned().map(wrap_phase).collect::<Vec<_>>();
+        &wrapped_phase
+    } else {
+        phase
+    };
+    match format {
+        Format::Png16 | Format::Tiff16 => write_image(path, phase, width, height),
+        Format::NpyF32 | Format::NpyF64 => {
+            let bytes = if format == Format::NpyF32 { 4 } else { 8 };
+            let writer = BufWriter::new(File::create(path)?);
+            npy::write_floats(writer, &[height, width], phase, bytes)
+        }
+        Format::RawF32 | Format::RawF64 => {
+            let (bytes, element) = if format == Format::RawF32 {
+                (4, "f32")
+            } else {
+                (8, "f64")
+            };
+            npy::write_raw_floats(BufWriter::new(File::create(path)?), phase, bytes)?;
+            let sidecar = Sidecar {
+                width,
+                height,
+                element: element.to_string(),
+                byte_order: "little".to_string(),
+                order: "row major".to_string(),
+                scale: 1.0,
+                wrapped,
+                device: device.name.clone(),
+                pixel_pitch: device.pixel_pitch,
+                wavelength: device.wavelength,
+            };
+            let file = File::create(sidecar_path(path))?;
+            serde_json::ser::to_writer_pretty(file, &sidecar).map_err(io::Error::from)
+        }
+    }
+}
+
+/// Render the data for its device and export the phase, with the aberration and flatness
+/// corrections added. If ```wrapped``` is false the phase isn't wrapped after the
+/// corrections are added (images are always wrapped)
+pub fn export<P: AsRef<Path>>(
+    path: P,
+    format: Format,
+    data: &SLMData,
+    calibration: &Calibration,
+    wrapped: bool,
+) -> io::Result<()> {
+    let phase = render::render_device_phase_unwrapped(data, calibration);
+    export_phase(
+        path,
+        format,
+        &phase,
+        data.device.width,
+        data.device.height,
+        wrapped,
+        &data.device,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+    use std::fs;
+
+    /// A path in the temporary directory to export to
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("phase-export-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn raw_exports_have_a_sidecar() {
+        let phase = [0.0, 1.5, -2.25, 7.0, 3.0, 0.125];
+        let device = DeviceProfile::default();
+        for &(format, bytes, element) in &[(Format::RawF32, 4, "f32"), (Format::RawF64, 8, "f64")] {
+            let path = temp_path(&format!("phase.{}.raw", element));
+            export_phase(&path, format, &phase, 3, 2, false, &device).unwrap();
+            let raw = fs::read(&path).unwrap();
+            let sidecar: Sidecar =
+                serde_json::from_reader(File::open(sidecar_path(&path)).unwrap()).unwrap();
+            fs::remove_file(&path).unwrap();
+            fs::remove_file(sidecar_path(&path)).unwrap();
+
+            assert_eq!(raw.len(), phase.len() * bytes);
+            let read = raw
+                .chunks(bytes)
+                .map(|chunk| {
+                    if bytes == 4 {
+                        f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as f64
+                    } else {
+                        let mut value = [0; 8];
+                        value.copy_from_slice(chunk);
+                        f64::from_le_bytes(value)
+                    }
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(read, phase);
+            assert_eq!(
+                sidecar,
+                Sidecar {
+                    width: 3,
+                    height: 2,
+                    element: element.to_string(),
+                    byte_order: "little".to_string(),
+                    order: "row major".to_string(),
+                    scale: 1.0,
+                    wrapped: false,
+                    device: device.name.clone(),
+                    pixel_pitch: device.pixel_pitch,
+                    wavelength: device.wavelength,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn images_have_65536_levels_for_2_pi() {
+        let phase = [
+            0.0,
+            PI / 2.0,
+            PI,
+            3.0 * PI / 2.0,
+            -PI / 2.0,
+            2.0 * PI - 1e-9,
+        ];
+        let device = DeviceProfile::default();
+        for &format in &[Format::Png16, Format::Tiff16] {
+            let path = temp_path(&format!("phase.{}", format.extension()));
+            // images are wrapped even if the phase isn't
+            export_phase(&path, format, &phase, 2, 3, false, &device).unwrap();
+            let image = image::open(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            let levels = match image {
+                DynamicImage::ImageLuma16(buffer) => {
+                    assert_eq!((buffer.width(), buffer.height()), (2, 3));
+                    buffer.into_raw()
+                }
+                _ => panic!("{:?} didn't export a 16 bit greyscale image", format),
+            };
+            assert_eq!(levels, vec![0, 16384, 32768, 49152, 49152, 65535]);
+        }
+    }
+
+    #[test]
+    fn rejects_phases_of_the_wrong_size() {
+        let path = temp_path("wrong-size.png");
+        let error = export_phase(
+            &path,
+            Format::Png16,
+            &[0.0; 5],
+            2,
+            3,
+            true,
+            &DeviceProfile::default(),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,114 @@
+//! This module contains the two dimensional Fourier transforms used to find the far field
+//! of a hologram. Fields are stored row by row, with the zero frequency at index 0.
+//! ```centred_index``` converts from coordinates measured from the centre of the far field
+use num_complex::Complex64;
+use rustfft::{FFTplanner, FFT};
+use std::sync::Arc;
+
+/// A planned two dimensional Fourier transform of a ```width``` x ```height``` field
+pub struct Fft2 {
+    width: usize,
+    height: usize,
+    inverse: bool,
+    rows: Arc<dyn FFT<f64>>,
+    columns: Arc<dyn FFT<f64>>,
+}
+
+impl Fft2 {
+    /// Plan a forward transform, or an inverse transform if ```inverse``` is true
+    pub fn new(width: usize, height: usize, inverse: bool) -> Fft2 {
+        let mut planner = FFTplanner::new(inverse);
+        Fft2 {
+            width,
+            height,
+            inverse,
+            rows: planner.plan_fft(width),
+            columns: planner.plan_fft(height),
+        }
+    }
+
+    /// Transform the field in place.
+    /// The inverse transform is normalised, so a forward then inverse transform gives back the field
+    pub fn process(&self, field: &mut [Complex64]) {
+        let (width, height) = (self.width, self.height);
+        assert_eq!(field.len(), width * height);
+        let mut output = vec![Complex64::new(0.0, 0.0); field.len()];
+        self.rows.process_multi(field, &mut output);
+        transpose(&output, field, width, height);
+        self.columns.process_multi(field, &mut output);
+        transpose(&output, field, height, width);
+        if self.inverse {
+            let scale = 1.0 / field.len() as f64;
+            for f in field.iter_mut() {
+                *f *= scale;
+            }
+        }
+    }
+}
+
+/// Transpose the ```width``` x ```height``` array ```input``` into ```output```
+fn transpose(input: &[Complex64], output: &mut [Complex64], width: usize, height: usize) {
+    for y in 0..height {
+        for x in 0..width {
+            output[x * height + y] = input[y * width + x];
+        }
+    }
+}
+
+/// The index of the far field pixel (x, y) measured from the centre of the far field,
+/// where the zero order is. Coordinates outside of the field wrap around
+pub fn centred_index(x: i64, y: i64, width: usize, height: usize) -> usize {
+    let x = x.rem_euclid(width as i64) as usize;
+    let y = y.rem_euclid(height as i64) as usize;
+    y * width + x
+}
+
+/// Move the zero frequency from index 0 to the centre of the field, for displaying the far field
+pub fn shift<T: Copy>(field: &[T], width: usize, height: usize) -> Vec<T> {
+    let (half_width, half_height) = (width as i64 / 2, height as i64 / 2);
+    (0..height as i64)
+        .flat_map(|y| (0..width as i64).map(move |x| (x, y)))
+        .map(|(x, y)| field[centred_index(x - half_width, y - half_height, width, height)])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse_transforms_undo_forward_transforms() {
+        let (width, height) = (6, 5);
+        let field = (0..width * height)
+            .map(|i| Complex64::new((i as f64 * 0.7).sin(), (i as f64 * 1.3).cos() - 0.2))
+            .collect::<Vec<_>>();
+        let mut transformed = field.clone();
+        Fft2::new(width, height, false).process(&mut transformed);
+        assert!(transformed
+            .iter()
+            .zip(field.iter())
+            .any(|(t, f)| (t - f).norm() > 1e-3));
+        Fft2::new(width, height, true).process(&mut transformed);
+        for (t, f) in transformed.iter().zip(field.iter()) {
+            assert!((t - f).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn the_zero_frequency_is_the_sum() {
+        let mut field = vec![Complex64::new(0.5, -1.0); 12];
+        Fft2::new(4, 3, false).process(&mut field);
+        assert!((field[0] - Complex64::new(6.0, -12.0)).norm() < 1e-12);
+        assert!(field[1..].iter().all(|f| f.norm() < 1e-12));
+    }
+
+    #[test]
+    fn shifting_centres_the_zero_frequency() {
+        let (width, height) = (4, 3);
+        let field = (0..width * height).collect::<Vec<_>>();
+        let shifted = shift(&field, width, height);
+        assert_eq!(shifted[height / 2 * width + width / 2], 0);
+        assert_eq!(shifted, vec![10, 11, 8, 9, 2, 3, 0, 1, 6, 7, 4, 5]);
+        assert_eq!(centred_index(-1, -1, width, height), 11);
+    }
+}
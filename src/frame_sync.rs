@@ -0,0 +1,307 @@
+//! This module tells other equipment, such as a camera, when a new hologram is on the SLM.
+//! The sequence player calls each ```FrameSync``` in turn after a frame has been drawn in the
+//! output window. They are called on another thread, so waiting in them doesn't hold up the
+//! player
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::remote::{Event, Events};
+
+/// A frame which has been presented on the SLM
+#[derive(Clone, Debug, PartialEq)]
+pub struct PresentedFrame {
+    /// The index of the frame in the sequence
+    pub index: usize,
+    /// The values of the fields swept for the frame, labelled as in the sequence
+    pub parameters: Vec<(String, f64)>,
+}
+
+/// Something which is done after each frame is presented
+pub trait FrameSync: Send {
+    fn frame_presented(&mut self, frame: &PresentedFrame) -> io::Result<()>;
+}
+
+/// Wait for the liquid crystal to settle, before the syncs after this one are called
+pub struct Delay {
+    pub settle: Duration,
+}
+
+impl FrameSync for Delay {
+    fn frame_presented(&mut self, _: &PresentedFrame) -> io::Result<()> {
+        thread::sleep(self.settle);
+        Ok(())
+    }
+}
+
+/// Send a ```frame``` event to the remote control connections which subscribed
+pub struct RemoteMessage {
+    pub events: Events,
+}
+
+impl FrameSync for RemoteMessage {
+    fn frame_presented(&mut self, frame: &PresentedFrame) -> io::Result<()> {
+        self.events.send(&Event::Frame {
+            index: frame.index,
+            parameters: frame.parameters.clone(),
+        });
+        Ok(())
+    }
+}
+
+/// A digital line which can be set high or low, such as a trigger input of a camera
+pub trait Line: Send {
+    fn set(&mut self, high: bool) -> io::Result<()>;
+}
+
+/// A GPIO pin exported through sysfs, set by writing to its value file,
+/// such as ```/sys/class/gpio/gpio17/value```
+pub struct GpioLine {
+    pub path: PathBuf,
+}
+
+impl Line for GpioLine {
+    fn set(&mut self, high: bool) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        file.write_all(if high { b"1" } else { b"0" })
+    }
+}
+
+/// A serial port, or anything else written to, which is sent one string when the line goes
+/// high and another when it goes low.
+/// The port's baud rate is left as it is set up, by ```stty``` for example
+pub struct SerialLine<W: Write + Send> {
+    pub port: W,
+    pub high: Vec<u8>,
+    pub low: Vec<u8>,
+}
+
+impl SerialLine<File> {
+    /// Open a serial port, which is sent ```1``` when the line goes high and ```0``` when it
+    /// goes low
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<SerialLine<File>> {
+        Ok(SerialLine {
+            port: OpenOptions::new().write(true).open(path)?,
+            high: b"1".to_vec(),
+            low: b"0".to_vec(),
+        })
+    }
+}
+
+impl<W: Write + Send> Line for SerialLine<W> {
+    fn set(&mut self, high: bool) -> io::Result<()> {
+        self.port
+            .write_all(if high { &self.high } else { &self.low })?;
+        self.port.flush()
+    }
+}
+
+/// A serial port which keeps what is written to it, for checking a ```SerialLine``` without
+/// any hardware. Clones share what is written
+#[derive(Clone, Default)]
+pub struct MockPort {
+    pub written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Write for MockPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written
+            .lock()
+            .map_err(|_| io::Error::other("the mock port was poisoned"))?
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Open the line at the path: a GPIO pin if the path is a sysfs ```value``` file, otherwise a
+/// serial port
+pub fn open_line<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Line>> {
+    let path = path.as_ref();
+    if path.file_name().is_some_and(|name| name == "value") {
+        Ok(Box::new(GpioLine {
+            path: path.to_path_buf(),
+        }))
+    } else {
+        Ok(Box::new(SerialLine::open(path)?))
+    }
+}
+
+/// Pulse a line high for a time after each frame
+pub struct Toggle {
+    pub line: Box<dyn Line>,
+    pub pulse: Duration,
+}
+
+impl FrameSync for Toggle {
+    fn frame_presented(&mut self, _: &PresentedFrame) -> io::Result<()> {
+        self.line.set(true)?;
+        thread::sleep(self.pulse);
+        self.line.set(false)
+    }
+}
+
+/// Calls the syncs in order for each presented frame, on another thread.
+/// The thread stops once this is dropped and the frames already presented are synced
+pub struct SyncThread {
+    sender: mpsc::Sender<PresentedFrame>,
+}
+
+impl SyncThread {
+    /// Start the thread. ```failed``` is called on the thread with the frame and the error
+    /// whenever a sync fails, and the syncs after it are still called
+    pub fn spawn<F>(mut syncs: Vec<Box<dyn FrameSync>>, mut failed: F) -> SyncThread
+    where
+        F: FnMut(&PresentedFrame, io::Error) + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel::<PresentedFrame>();
+        thread::spawn(move || {
+            for frame in receiver {
+                for sync in syncs.iter_mut() {
+                    if let Err(e) = sync.frame_presented(&frame) {
+                        failed(&frame, e);
+                    }
+                }
+            }
+        });
+        SyncThread { sender }
+    }
+
+    /// Sync a frame which has just been presented
+    pub fn frame_presented(&self, frame: PresentedFrame) {
+        let _ = self.sender.send(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn frame(index: usize) -> PresentedFrame {
+        PresentedFrame {
+            index,
+            parameters: vec![(String::from("0.l"), index as f64)],
+        }
+    }
+
+    /// Sends its name and each frame it is called with
+    struct Recording {
+        name: &'static str,
+        sender: mpsc::Sender<(&'static str, PresentedFrame)>,
+    }
+
+    impl FrameSync for Recording {
+        fn frame_presented(&mut self, frame: &PresentedFrame) -> io::Result<()> {
+            let _ = self.sender.send((self.name, frame.clone()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn toggle_writes_high_then_low_for_each_frame() {
+        let port = MockPort::default();
+        let mut toggle = Toggle {
+            line: Box::new(SerialLine {
+                port: port.clone(),
+                high: b"H".to_vec(),
+                low: b"L".to_vec(),
+            }),
+            pulse: Duration::from_millis(1),
+        };
+        toggle.frame_presented(&frame(0)).unwrap();
+        assert_eq!(*port.written.lock().unwrap(), b"HL");
+        toggle.frame_presented(&frame(1)).unwrap();
+        assert_eq!(*port.written.lock().unwrap(), b"HLHL");
+    }
+
+    #[test]
+    fn sync_thread_calls_the_syncs_in_order() {
+        let (sender, receiver) = mpsc::channel();
+        let syncs: Vec<Box<dyn FrameSync>> = vec![
+            Box::new(Recording {
+                name: "first",
+                sender: sender.clone(),
+            }),
+            Box::new(Recording {
+                name: "second",
+                sender,
+            }),
+        ];
+        let sync_thread = SyncThread::spawn(syncs, |_, e| panic!("{}", e));
+        for index in 0..3 {
+            sync_thread.frame_presented(frame(index));
+        }
+        // the syncs are dropped with the thread once the frames are synced, ending the channel
+        drop(sync_thread);
+        let received = receiver
+            .iter()
+            .map(|(name, frame)| (name, frame.index))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            received,
+            vec![
+                ("first", 0),
+                ("second", 0),
+                ("first", 1),
+                ("second", 1),
+                ("first", 2),
+                ("second", 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn delay_waits_for_the_settle_time() {
+        let settle = Duration::from_millis(50);
+        let mut delay = Delay { settle };
+        let start = Instant::now();
+        delay.frame_presented(&frame(0)).unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= settle, "waited {:?}", elapsed);
+    }
+
+    /// Fails to sync every frame
+    struct Failing;
+
+    impl FrameSync for Failing {
+        fn frame_presented(&mut self, _: &PresentedFrame) -> io::Result<()> {
+            Err(io::Error::other("the line is unplugged"))
+        }
+    }
+
+    #[test]
+    fn sync_thread_reports_failures_and_carries_on() {
+        let (sender, receiver) = mpsc::channel();
+        let (failures, failed) = mpsc::channel();
+        let syncs: Vec<Box<dyn FrameSync>> = vec![
+            Box::new(Failing),
+            Box::new(Recording {
+                name: "after",
+                sender,
+            }),
+        ];
+        let sync_thread = SyncThread::spawn(syncs, move |frame, e| {
+            let _ = failures.send((frame.index, e.to_string()));
+        });
+        sync_thread.frame_presented(frame(4));
+        drop(sync_thread);
+        assert_eq!(
+            failed.iter().collect::<Vec<_>>(),
+            vec![(4, String::from("the line is unplugged"))]
+        );
+        assert_eq!(
+            receiver
+                .iter()
+                .map(|(name, frame)| (name, frame.index))
+                .collect::<Vec<_>>(),
+            vec![("after", 4)]
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! This module designs holograms for arbitrary far field intensities with the
+//! Gerchberg–Saxton algorithm, and the weighted Gerchberg–Saxton algorithm for arrays of spots.
+//! The hologram is lit by a uniform beam, and the far field is its Fourier transform,
+//! with the zero order in the centre of the far field
+use image::imageops::{self, FilterType};
+use num_complex::Complex64;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::io;
+use std::path::Path;
+
+use crate::fourier::{centred_index, Fft2};
+use crate::pattern::trap_phase;
+use crate::phase_map::PhaseMap;
+use crate::render::wrap_phase;
+use crate::slm_data::TrapsData;
+
+/// The largest number of pixels sampled when designing traps
+const MAX_TRAP_SAMPLES: usize = 65536;
+
+/// A spot in the far field
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Spot {
+    /// The position of the spot in far field pixels, measured from the zero order
+    pub x: f64,
+    pub y: f64,
+    /// The relative intensity of the spot
+    pub weight: f64,
+}
+
+impl Spot {
+    /// Read a list of spots, written as ```x, y``` or ```x, y, weight``` separated by ```;```.
+    /// Spots without a weight have a weight of 1
+    pub fn parse_list(text: &str) -> Result<Vec<Spot>, String> {
+        text.split(';')
+            .map(str::trim)
+            .filter(|spot| !spot.is_empty())
+            .map(|spot| {
+                let values = spot
+                    .split(',')
+                    .map(|v| v.trim().parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| format!("Couldn't read the spot \"{}\"", spot))?;
+                match values.as_slice() {
+                    [x, y] => Ok(Spot {
+                        x: *x,
+                        y: *y,
+                        weight: 1.0,
+                    }),
+                    [x, y, weight] => Ok(Spot {
+                        x: *x,
+                        y: *y,
+                        weight: *weight,
+                    }),
+                    _ => Err(format!(
+                        "The spot \"{}\" should be x, y or x, y, weight",
+                        spot
+                    )),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The far field amplitude which the hologram should make
+#[derive(Clone, Debug, PartialEq)]
+pub struct Target {
+    pub width: usize,
+    pub height: usize,
+    /// The amplitude of each far field pixel, with the zero order at index 0
+    pub amplitude: Vec<f64>,
+}
+
+/// Check the hologram has some pixels, so there is a far field to put the target in
+fn check_size(width: usize, height: usize) -> io::Result<()> {
+    if width == 0 || height == 0 {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("a {}x{} hologram has no pixels", width, height),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+impl Target {
+    /// A target with the given spots, for a ```width``` x ```height``` hologram.
+    /// Each spot is put on the nearest far field pixel
+    pub fn from_spots(spots: &[Spot], width: usize, height: usize) -> io::Result<Target> {
+        check_size(width, height)?;
+        let mut amplitude = vec![0.0; width * height];
+        for spot in spots {
+            let i = centred_index(
+                spot.x.round() as i64,
+                spot.y.round() as i64,
+                width,
+                height,
+            );
+            amplitude[i] = (amplitude[i] * amplitude[i] + spot.weight.max(0.0)).sqrt();
+        }
+        Ok(Target {
+            width,
+            height,
+            amplitude,
+        })
+    }
+
+    /// A target with the intensity of a greyscale image, for a ```width``` x ```height``` hologram.
+    /// The image is centred on the zero order, and shrunk to fit if it is too large
+    pub fn from_image<P: AsRef<Path>>(path: P, width: usize, height: usize) -> io::Result<Target> {
+        check_size(width, height)?;
+        let mut image = image::open(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+            .to_luma8();
+        if image.width() as usize > width || image.height() as usize > height {
+            let scale = (width as f64 / f64::from(image.width()))
+                .min(height as f64 / f64::from(image.height()));
+            let new_width = ((f64::from(image.width()) * scale) as u32).max(1);
+            let new_height = ((f64::from(image.height()) * scale) as u32).max(1);
+            image = imageops::resize(&image, new_width, new_height, FilterType::Triangle);
+        }
+        let (image_width, image_height) = (image.width() as i64, image.height() as i64);
+        let mut amplitude = vec![0.0; width * height];
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let i = centred_index(
+                i64::from(x) - image_width / 2,
+                i64::from(y) - image_height / 2,
+                width,
+                height,
+            );
+            amplitude[i] = (f64::from(pixel[0]) / 255.0).sqrt();
+        }
+        Ok(Target {
+            width,
+            height,
+            amplitude,
+        })
+    }
+}
+
+/// The algorithm used to design the hologram
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    /// Gerchberg–Saxton, which replaces the far field amplitude with the target each iteration
+    GerchbergSaxton,
+    /// Weighted Gerchberg–Saxton, which also reweights the target each iteration to even out
+    /// the errors in the bright pixels. This makes much more uniform arrays of spots
+    Weighted,
+}
+
+/// A generator of the starting phase, so the same target always gives the same hologram
+struct XorShift(u64);
+
+impl XorShift {
+    /// A random number in [0, 1)
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn next_phase(&mut self) -> f64 {
+        self.next() * 2.0 * PI
+    }
+}
+
+/// Design a hologram which makes the target in the far field, by running the algorithm for
+/// the given number of iterations.
+/// ```progress``` is called with the number of iterations done and the total after each iteration
+pub fn run<F: FnMut(usize, usize)>(
+    target: &Target,
+    algorithm: Algorithm,
+    iterations: usize,
+    mut progress: F,
+) -> PhaseMap {
+    let (width, height) = (target.width, target.height);
+    let forward = Fft2::new(width, height, false);
+    let inverse = Fft2::new(width, height, true);
+    let mut random = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut phase = (0..width * height)
+        .map(|_| random.next_phase())
+        .collect::<Vec<_>>();
+    let mut weights = vec![1.0; width * height];
+    let mut field = vec![Complex64::new(0.0, 0.0); width * height];
+    for iteration in 0..iterations {
+        for (f, &p) in field.iter_mut().zip(phase.iter()) {
+            *f = Complex64::from_polar(&1.0, &p);
+        }
+        forward.process(&mut field);
+        if algorithm == Algorithm::Weighted {
+            reweight(&field, &target.amplitude, &mut weights);
+        }
+        for ((f, &t), &w) in field.iter_mut().zip(target.amplitude.iter()).zip(weights.iter()) {
+            *f = Complex64::from_polar(&(t * w), &f.arg());
+        }
+        inverse.process(&mut field);
+        for (p, f) in phase.iter_mut().zip(field.iter()) {
+            *p = wrap_phase(f.arg());
+        }
+        progress(iteration + 1, iterations);
+    }
+    PhaseMap {
+        width,
+        height,
+        phase,
+    }
+}
+
+/// Update the weights of the weighted algorithm. Pixels which are dimmer than the target
+/// (relative to the average over the target) have their weight raised, and brighter pixels lowered
+fn reweight(field: &[Complex64], target: &[f64], weights: &mut [f64]) {
+    let ratios = field
+        .iter()
+        .zip(target.iter())
+        .map(|(f, &t)| if t > 0.0 { f.norm() / t } else { 0.0 })
+        .collect::<Vec<_>>();
+    let lit = target.iter().filter(|&&t| t > 0.0).count();
+    if lit == 0 {
+        return;
+    }
+    let mean = ratios.iter().sum::<f64>() / lit as f64;
+    for (w, &r) in weights.iter_mut().zip(ratios.iter()) {
+        if r > 0.0 {
+            *w *= mean / r;
+        }
+    }
+}
+
+/// Find the gain and phase of the gratings of the traps, for a ```width``` x ```height``` hologram.
+/// With no iterations the traps are plain gratings and lenses with random phases.
+/// Otherwise the weighted Gerchberg–Saxton algorithm for traps (Di Leonardo et al.,
+/// Optics Express 15, 1913, 2007) is run, which evens out the intensities of the traps.
+/// Large holograms are sampled at random pixels, which is enough to find the trap fields
+pub fn design_traps(traps: &mut TrapsData, width: usize, height: usize) {
+    let mut random = XorShift(0x9e37_79b9_7f4a_7c15);
+    for trap in traps.traps.iter_mut() {
+        trap.gain = 1.0;
+        trap.phase = random.next_phase();
+    }
+    if traps.iterations == 0 || traps.traps.is_empty() || width * height == 0 {
+        return;
+    }
+    let samples = if width * height <= MAX_TRAP_SAMPLES {
+        (0..width * height)
+            .map(|i| ((i % width) as f64, (i / width) as f64))
+            .collect::<Vec<_>>()
+    } else {
+        (0..MAX_TRAP_SAMPLES)
+            .map(|_| {
+                (
+                    (random.next() * width as f64).floor(),
+                    (random.next() * height as f64).floor(),
+                )
+            })
+            .collect()
+    };
+    let c = traps.c;
+    // The grating and lens of each trap at each sample, with the trap index first
+    let gratings = traps
+        .traps
+        .iter()
+        .map(|trap| {
+            samples
+                .iter()
+                .map(|&(x, y)| {
+                    Complex64::from_polar(&1.0, &trap_phase(trap, x, y, x - c.0, y - c.1))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let amplitudes = traps
+        .traps
+        .iter()
+        .map(|trap| trap.weight.max(0.0).sqrt())
+        .collect::<Vec<_>>();
+    let mut gains = vec![1.0; traps.traps.len()];
+    let mut phases = traps.traps.iter().map(|trap| trap.phase).collect::<Vec<_>>();
+    let mut hologram = vec![Complex64::new(0.0, 0.0); samples.len()];
+    for _ in 0..traps.iterations {
+        for (j, h) in hologram.iter_mut().enumerate() {
+            let field: Complex64 = gratings
+                .iter()
+                .enumerate()
+                .map(|(m, grating)| {
+                    grating[j] * Complex64::from_polar(&(amplitudes[m] * gains[m]), &phases[m])
+                })
+                .sum();
+            *h = Complex64::from_polar(&1.0, &field.arg());
+        }
+        let fields = gratings
+            .iter()
+            .map(|grating| {
+                grating
+                    .iter()
+                    .zip(hologram.iter())
+                    .map(|(g, h)| h * g.conj())
+                    .sum::<Complex64>()
+                    / samples.len() as f64
+            })
+            .collect::<Vec<_>>();
+        let lit = amplitudes.iter().filter(|&&a| a > 0.0).count();
+        let mean = fields
+            .iter()
+            .zip(amplitudes.iter())
+            .filter(|(_, &a)| a > 0.0)
+            .map(|(f, a)| f.norm() / a)
+            .sum::<f64>()
+            / lit.max(1) as f64;
+        for (m, field) in fields.iter().enumerate() {
+            if amplitudes[m] > 0.0 && field.norm() > 0.0 {
+                gains[m] *= mean / (field.norm() / amplitudes[m]);
+            }
+            phases[m] = field.arg();
+        }
+    }
+    for ((trap, gain), phase) in traps.traps.iter_mut().zip(gains).zip(phases) {
+        trap.gain = gain;
+        trap.phase = phase;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Pattern;
+    use crate::slm_data::Trap;
+
+    fn spot(x: f64, y: f64, weight: f64) -> Spot {
+        Spot { x, y, weight }
+    }
+
+    /// The far field intensity of a hologram lit by a uniform beam
+    fn far_field_intensity(hologram: &PhaseMap) -> Vec<f64> {
+        let mut field = hologram
+            .phase
+            .iter()
+            .map(|&p| Complex64::from_polar(&1.0, &p))
+            .collect::<Vec<_>>();
+        Fft2::new(hologram.width, hologram.height, false).process(&mut field);
+        field.iter().map(|f| f.norm_sqr()).collect()
+    }
+
+    /// The distance between two phases, allowing for wrapping
+    fn phase_difference(a: f64, b: f64) -> f64 {
+        let difference = wrap_phase(a - b);
+        difference.min(2.0 * PI - difference)
+    }
+
+    /// The difference between the brightest and dimmest spot, over their sum
+    fn spread(spots: &[Spot], algorithm: Algorithm) -> f64 {
+        let (width, height) = (64, 64);
+        let target = Target::from_spots(spots, width, height).unwrap();
+        let hologram = run(&target, algorithm, 30, |_, _| ());
+        let intensity = far_field_intensity(&hologram);
+        let spots = spots
+            .iter()
+            .map(|spot| intensity[centred_index(spot.x as i64, spot.y as i64, width, height)])
+            .collect::<Vec<_>>();
+        let max = spots.iter().cloned().fold(f64::MIN, f64::max);
+        let min = spots.iter().cloned().fold(f64::MAX, f64::min);
+        (max - min) / (max + min)
+    }
+
+    #[test]
+    fn one_spot_makes_a_linear_grating() {
+        let (width, height) = (32, 16);
+        let target = Target::from_spots(&[spot(5.0, -3.0, 1.0)], width, height).unwrap();
+        let hologram = run(&target, Algorithm::GerchbergSaxton, 5, |_, _| ());
+        for y in 0..height {
+            for x in 0..width {
+                let grating =
+                    2.0 * PI * (5.0 * x as f64 / width as f64 - 3.0 * y as f64 / height as f64);
+                let phase = hologram.phase[y * width + x] - hologram.phase[0];
+                assert!(phase_difference(phase, grating) < 1e-6, "at {}, {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn weighting_evens_out_arrays_of_spots() {
+        let spots = (0..3)
+            .flat_map(|i| {
+                (0..3).map(move |j| spot(4.0 + 5.0 * i as f64, 3.0 + 6.0 * j as f64, 1.0))
+            })
+            .collect::<Vec<_>>();
+        let plain = spread(&spots, Algorithm::GerchbergSaxton);
+        let weighted = spread(&spots, Algorithm::Weighted);
+        assert!(weighted < plain, "{} isn't less than {}", weighted, plain);
+    }
+
+    #[test]
+    fn traps_without_iterations_are_a_plain_superposition() {
+        let mut traps = TrapsData {
+            traps: vec![
+                Trap::new((0.3, -0.1)),
+                Trap {
+                    z: 1e-4,
+                    weight: 2.0,
+                    ..Trap::new((-0.2, 0.25))
+                },
+            ],
+            a: 1.0,
+            c: (8.0, 8.0),
+            iterations: 0,
+        };
+        design_traps(&mut traps, 16, 16);
+        for trap in &traps.traps {
+            assert_eq!(trap.gain, 1.0);
+        }
+        for &(x, y) in &[(0.0, 0.0), (3.0, 11.0), (15.0, 7.0)] {
+            let superposition: Complex64 = traps
+                .traps
+                .iter()
+                .map(|trap| {
+                    Complex64::from_polar(
+                        &trap.weight.sqrt(),
+                        &(trap_phase(trap, x, y, x - 8.0, y - 8.0) + trap.phase),
+                    )
+                })
+                .sum();
+            let field = traps.field(x, y);
+            assert!((field - superposition / superposition.norm()).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn rejects_holograms_without_pixels() {
+        let spots = [spot(1.0, 1.0, 1.0)];
+        for &(width, height) in &[(0, 10), (10, 0), (0, 0)] {
+            let error = Target::from_spots(&spots, width, height).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            let error = Target::from_image("missing.png", width, height).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn parses_lists_of_spots() {
+        assert_eq!(
+            Spot::parse_list("10, 0, 2; -5.5,3 ;0, -20, 0.5").unwrap(),
+            vec![
+                spot(10.0, 0.0, 2.0),
+                spot(-5.5, 3.0, 1.0),
+                spot(0.0, -20.0, 0.5)
+            ]
+        );
+        assert_eq!(Spot::parse_list("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn ignores_a_trailing_separator() {
+        assert_eq!(
+            Spot::parse_list("1, 2; 3, 4;").unwrap(),
+            vec![spot(1.0, 2.0, 1.0), spot(3.0, 4.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn spots_without_a_weight_have_a_weight_of_1() {
+        assert_eq!(Spot::parse_list("7, 8").unwrap(), vec![spot(7.0, 8.0, 1.0)]);
+    }
+
+    #[test]
+    fn rejects_spots_which_arent_numbers() {
+        assert!(Spot::parse_list("1, two").is_err());
+        assert!(Spot::parse_list("1, 2; x").is_err());
+        assert!(Spot::parse_list("1, 2, 3, 4").is_err());
+        assert!(Spot::parse_list("1").is_err());
+    }
+}
@@ -1,47 +1,45 @@
 //! This is the main window that the use interacts with to generate phase patterns
 
 use gtk::{
-    BoxExt, ButtonExt, ContainerExt, DialogExt, DrawingArea, FileChooserExt, NotebookExt,
+    BoxExt, ButtonExt, ContainerExt, DialogExt, FileChooserExt, NotebookExt,
     ResponseType, WidgetExt,
 };
 use gtk::prelude::*;
+use gdk::ContextExt;
 use relm::{Component, ContainerWidget, Relm, Update, Widget, DrawHandler};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::*;
+use std::f64::consts::PI;
 use std::fs::File;
-use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
 
 use self::SLMControllerMsg::*;
 
+use crate::output_window::{self, OutputWindow, OutputWindowMsg};
 use crate::pattern_container::{PatternContainer, PatternContainerMsg};
-use crate::slm_data::*;
+use crate::sequence_controller::{Editor, EditorAction, SequenceController, SequenceControllerMsg};
+use crate::simulation_controller::{RenderedPhase, SimulationController, SimulationControllerMsg};
+use crate::zernike_controller::ZernikeController;
+use phase::device::{self, Calibration, DeviceProfile};
+use phase::export::{self, Format};
+use phase::gerchberg_saxton::{self, Algorithm, Spot, Target};
+use phase::pattern::{Pattern, PatternKind};
+use phase::phase_map::PhaseMap;
+use phase::render;
+use phase::remote::{self, Command, Events, Reply};
+use phase::zernike::{self, ZernikeCorrection};
+use phase::slm_data::*;
 
-macro_rules! update_from_pattern_spinner {
-    ($self:ident, $c_id:ident, $p_id:ident, $x:ident, $l:tt) => {
-        if let Some(container) = $self.model.pattern_data_containers.get_mut(&$c_id) {
-            if let Some(pattern) = container.patterns.get_mut(&$p_id) {
-                pattern.$l = $x;
-            }
-        };
-    };
-    ($self:ident, $c_id:ident, $p_id:ident, $x:ident, $l:tt, $n:tt) => {
-        if let Some(container) = $self.model.pattern_data_containers.get_mut(&$c_id) {
-            if let Some(pattern) = container.patterns.get_mut(&$p_id) {
-                pattern.$l.$n = $x;
-            }
-        };
-    };
-}
 macro_rules! update_from_container_spinner {
     ($self:ident, $c_id:ident, $x:ident, $l:tt) => {
         if let Some(container) = $self.model.pattern_data_containers.get_mut(&$c_id) {
             container.$l = $x;
-        };
+        }
     };
     ($self:ident, $c_id:ident, $x:ident, $l:tt, $n:tt) => {
         if let Some(container) = $self.model.pattern_data_containers.get_mut(&$c_id) {
             container.$l.$n = $x;
-        };
+        }
     };
 }
 
@@ -50,7 +48,123 @@ pub struct SLMControllerModel {
     /// A vector of the pattern containers. For use in the gtk notebook
     pub pattern_data_containers: HashMap<usize, PatternContainerData>,
     current_container_id: usize,
+    /// The SLM which the patterns are displayed on
+    pub device: DeviceProfile,
+    /// The calibration loaded from the files the device profile refers to,
+    /// which is shared with the threads rendering the patterns
+    calibration: Arc<Calibration>,
+    /// The aberration correction added to the whole screen
+    pub correction: ZernikeCorrection,
     image_buffer: gdk_pixbuf::Pixbuf,
+    /// Whether clicks on the preview edit the traps of the current container
+    trap_mode: bool,
+    /// The trap being dragged on the preview
+    dragged_trap: Option<usize>,
+    /// The containers and patterns of the traps which changed while traps were being designed,
+    /// so they are designed again once the design finishes
+    traps_pending: HashSet<(usize, usize)>,
+    /// Whether the patterns changed while they were being rendered, so they are rendered again
+    /// once the render finishes
+    render_pending: bool,
+    /// The address the remote control server listens on, if it is started
+    remote_address: Option<String>,
+    /// Whether a sequence is playing, so the frames of the sequence are shown on the SLM
+    /// instead of the rendered patterns
+    sequence_playing: bool,
+}
+
+/// The time in ms between a remote control ```quit``` and closing, so the reply is sent first
+pub(crate) const QUIT_DELAY: u32 = 100;
+
+/// Load the calibration for the device, falling back to no calibration if it can't be read
+pub(crate) fn load_calibration(device: &DeviceProfile) -> Calibration {
+    Calibration::load(device).unwrap_or_else(|e| {
+        eprintln!("Couldn't load the calibration for {}: {}", device.name, e);
+        Calibration::default()
+    })
+}
+
+/// Create a black image buffer with the resolution of the device
+fn new_image_buffer(device: &DeviceProfile) -> gdk_pixbuf::Pixbuf {
+    gdk_pixbuf::Pixbuf::new(
+        gdk_pixbuf::Colorspace::Rgb,
+        false,
+        8,
+        device.width as i32,
+        device.height as i32,
+    )
+    .unwrap()
+}
+
+/// Create an image buffer from an 8 bit greyscale image with one byte per pixel
+pub(crate) fn grey_to_pixbuf(grey: &[u8], width: i32, height: i32) -> gdk_pixbuf::Pixbuf {
+    let rgb = grey.iter().flat_map(|&g| vec![g, g, g]).collect::<Vec<_>>();
+    gdk_pixbuf::Pixbuf::new_from_mut_slice(
+        rgb,
+        gdk_pixbuf::Colorspace::Rgb,
+        false,
+        8,
+        width,
+        height,
+        width * 3,
+    )
+}
+
+/// Ask which device the patterns will be displayed on.
+/// The device is either one of the presets, or a profile loaded from a json file
+pub fn choose_device_profile() -> DeviceProfile {
+    use gtk::DialogFlags;
+    let presets = device::presets();
+    let dialog = gtk::Dialog::new_with_buttons(
+        Some("Choose SLM device"),
+        None::<&gtk::Window>,
+        DialogFlags::MODAL,
+        &[("_Ok", gtk::ResponseType::Accept)],
+    );
+    let preset_combo = gtk::ComboBoxText::new();
+    for preset in presets.iter() {
+        preset_combo.append_text(&preset.name);
+    }
+    preset_combo.set_active(0);
+    let profile_button =
+        gtk::FileChooserButton::new("Load device profile", gtk::FileChooserAction::Open);
+    let content = dialog.get_content_area();
+    content.pack_start(&gtk::Label::new("Device preset"), false, false, 0);
+    content.pack_start(&preset_combo, false, false, 0);
+    content.pack_start(&gtk::Label::new("or device profile file"), false, false, 0);
+    content.pack_start(&profile_button, false, false, 0);
+    dialog.show_all();
+    // ask again until the chosen profile can be loaded, so a broken profile isn't replaced
+    // by a preset without the user knowing
+    let device = loop {
+        dialog.run();
+        let filename = match profile_button.get_filename() {
+            Some(filename) => filename,
+            None => break presets[preset_combo.get_active().unwrap_or(0) as usize].clone(),
+        };
+        match DeviceProfile::load(&filename) {
+            Ok(device) => break device,
+            Err(e) => show_error(
+                Some(dialog.upcast_ref()),
+                &format!("Couldn't load the device profile {}: {}", filename.display(), e),
+            ),
+        }
+    };
+    dialog.emit_close();
+    device
+}
+
+/// Show an error message in a dialog over the window, and wait for it to be dismissed
+pub(crate) fn show_error(parent: Option<&gtk::Window>, message: &str) {
+    let dialog = gtk::MessageDialog::new(
+        parent,
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Error,
+        gtk::ButtonsType::Ok,
+        message,
+    );
+    dialog.run();
+    dialog.emit_close();
 }
 
 /// The messages which the slm controller accepts
@@ -60,17 +174,12 @@ pub enum SLMControllerMsg {
     RemoveTab,
     RemoveAllTabs,
     SaveContainers,
+    ExportPhase,
     LoadContainers,
     Quit,
-    AddController(usize, usize, PatternData),
+    AddController(usize, usize, PatternKind),
     RemoveController(usize, usize),
-    UpdatePatternL(usize, usize, i32),
-    UpdatePatternA(usize, usize, f64),
-    UpdatePatternKx(usize, usize, f64),
-    UpdatePatternKy(usize, usize, f64),
-    UpdatePatternCx(usize, usize, f64),
-    UpdatePatternCy(usize, usize, f64),
-    UpdatePatternPhase(usize, usize, f64),
+    UpdatePatternParameter(usize, usize, String, f64),
     UpdateContainerCx(usize, f64),
     UpdateContainerCy(usize, f64),
     UpdateContainerScaleX(usize, f64),
@@ -79,7 +188,31 @@ pub enum SLMControllerMsg {
     UpdateContainerTLY(usize, f64),
     UpdateContainerBRX(usize, f64),
     UpdateContainerBRY(usize, f64),
+    UpdateContainerCombination(usize, CombinationMode),
+    UpdateContainerIllumination(usize, Option<Illumination>),
     RenderPattern,
+    PatternRendered(usize, usize, Vec<f64>, Vec<u8>),
+    UpdateZernikeCentreX(f64),
+    UpdateZernikeCentreY(f64),
+    UpdateZernikeRadius(f64),
+    UpdateZernikeCoefficient(usize, f64),
+    SelectOutputMonitor(i32),
+    ShowOutputWindow(bool),
+    DesignHologram,
+    HologramProgress(usize, usize),
+    HologramDesigned(Result<PhaseMap, String>),
+    SetTrapMode(bool),
+    TrapPress(f64, f64, u32),
+    TrapMotion(f64, f64),
+    TrapRelease,
+    TrapScroll(f64, f64, bool, bool),
+    TrapsDesigned(usize, usize, TrapsData),
+    Remote(Command, mpsc::Sender<Reply>),
+    // sent by the sequence when it needs the patterns in the editor
+    SequenceEditor(EditorAction),
+    ShowSequenceFrame(Vec<u8>),
+    SequencePlaying(bool),
+    FramePresented,
 }
 
 /// The relm slm controller struct
@@ -93,6 +226,35 @@ pub struct SLMController {
     pub relm: Relm<Self>,
     pub pattern_containers: HashMap<usize, Component<PatternContainer>>,
     pub draw_handler: DrawHandler<gtk::DrawingArea>,
+    /// the area which the rendered pattern is previewed in
+    pub drawing_area: gtk::DrawingArea,
+    /// the controls for the simulated light, which is shown beside the preview
+    pub simulation: Component<SimulationController>,
+    /// the controls for playing sequences of holograms on the SLM
+    pub sequence: Component<SequenceController>,
+    /// the fullscreen window which displays the pattern on the SLM
+    pub output_window: Component<OutputWindow>,
+    /// the window holding the aberration correction controls
+    pub zernike_window: gtk::ScrolledWindow,
+    pub zernike_controller: Component<ZernikeController>,
+    /// the image a hologram is designed to make
+    pub hologram_target: gtk::FileChooserButton,
+    /// the spots a hologram is designed to make, used instead of the image if there are any
+    pub hologram_spots: gtk::Entry,
+    pub hologram_iterations: gtk::SpinButton,
+    /// whether the weighted algorithm is used
+    pub hologram_weighted: gtk::CheckButton,
+    pub hologram_progress: gtk::ProgressBar,
+    /// receives the rendered patterns, while they are being rendered
+    render_channel: Option<relm::Channel<SLMControllerMsg>>,
+    /// receives the progress of the hologram being designed, while one is being designed
+    hologram_channel: Option<relm::Channel<SLMControllerMsg>>,
+    /// receives the designed traps, while traps are being designed
+    traps_channel: Option<relm::Channel<SLMControllerMsg>>,
+    /// receives the commands from the remote control server, if it is running
+    remote_channel: Option<relm::Channel<SLMControllerMsg>>,
+    /// the remote control connections which subscribed to events
+    remote_events: Events,
 }
 
 impl SLMController {
@@ -116,6 +278,567 @@ impl SLMController {
             .insert(self.model.current_container_id, container);
     }
 
+    /// Add a container with the given id, with its patterns keeping their ids.
+    /// The notebook's pages are kept in the order of the ids
+    fn insert_container(&mut self, id: usize, container: PatternContainerData) {
+        if self.pattern_containers.contains_key(&id) {
+            self.remove_container_widget(id);
+        }
+        let mut empty = container.clone();
+        empty.patterns.clear();
+        let widget = self
+            .container_notebook
+            .add_widget::<PatternContainer>((empty, self.relm.clone(), id));
+        let position = self
+            .model
+            .pattern_data_containers
+            .keys()
+            .filter(|&&other| other < id)
+            .count();
+        self.container_notebook
+            .reorder_child(widget.widget(), Some(position as u32));
+        for (&pattern_id, pattern) in container.patterns.iter() {
+            widget
+                .stream()
+                .emit(PatternContainerMsg::InsertPattern(pattern_id, pattern.clone()));
+        }
+        self.pattern_containers.insert(id, widget);
+        self.model.pattern_data_containers.insert(id, container);
+        self.model.current_container_id = self.model.current_container_id.max(id + 1);
+    }
+
+    /// Remove a container and its page, without asking
+    fn remove_container_widget(&mut self, id: usize) {
+        if let Some(widget) = self.pattern_containers.remove(&id) {
+            self.container_notebook.remove(widget.widget());
+        }
+        self.model.pattern_data_containers.remove(&id);
+    }
+
+    /// Remove every container and its page, without asking
+    fn clear_containers(&mut self) {
+        for (_, widget) in self.pattern_containers.drain() {
+            self.container_notebook.remove(widget.widget());
+        }
+        self.model.pattern_data_containers.clear();
+    }
+
+    /// Start the remote control server, which passes the commands it receives to the controller
+    fn start_remote(&mut self, address: &str) {
+        let stream = self.relm.stream().clone();
+        let (channel, sender) = relm::Channel::new(move |msg| stream.emit(msg));
+        let served = remote::serve(address, self.remote_events.clone(), move |command, reply| {
+            let _ = sender.send(Remote(command, reply));
+        });
+        match served {
+            Ok(address) => {
+                println!("Listening for remote control on {}", address);
+                self.remote_channel = Some(channel);
+            }
+            Err(e) => eprintln!("Couldn't start the remote control on {}: {}", address, e),
+        }
+    }
+
+    /// Carry out a command from the remote control, and update the editor to match
+    fn handle_remote(&mut self, command: Command) -> Reply {
+        let mut data = self.slm_data();
+        let reply = remote::apply(&command, &mut data);
+        if let Reply::Error { .. } = reply {
+            return reply;
+        }
+        match command {
+            Command::AddContainer { .. } => {
+                if let Reply::Ok { id: Some(id) } = reply {
+                    if let Some(container) = data.containers.remove(&id) {
+                        self.insert_container(id, container);
+                    }
+                }
+            }
+            Command::RemoveContainer { container } => self.remove_container_widget(container),
+            Command::RemoveAllContainers => self.clear_containers(),
+            Command::AddPattern { container, pattern } => {
+                if let (Reply::Ok { id: Some(id) }, Some(widget)) =
+                    (&reply, self.pattern_containers.get(&container))
+                {
+                    widget
+                        .stream()
+                        .emit(PatternContainerMsg::InsertPattern(*id, pattern));
+                }
+                self.model.pattern_data_containers = data.containers;
+            }
+            Command::RemovePattern { container, pattern } => {
+                if let Some(widget) = self.pattern_containers.get(&container) {
+                    widget
+                        .stream()
+                        .emit(PatternContainerMsg::DeletePattern(pattern));
+                }
+                self.model.pattern_data_containers = data.containers;
+            }
+            Command::SetParameter {
+                container,
+                pattern,
+                name,
+                value,
+            } => {
+                if let Some(widget) = self.pattern_containers.get(&container) {
+                    widget.stream().emit(PatternContainerMsg::SetPatternParameter(
+                        pattern, name, value,
+                    ));
+                }
+                self.model.pattern_data_containers = data.containers;
+            }
+            Command::SetCrop { container, .. }
+            | Command::SetPos { container, .. }
+            | Command::SetScale { container, .. } => {
+                if let (Some(widget), Some(data)) = (
+                    self.pattern_containers.get(&container),
+                    data.containers.get(&container),
+                ) {
+                    widget
+                        .stream()
+                        .emit(PatternContainerMsg::SetGeometry(data.clone()));
+                }
+                self.model.pattern_data_containers = data.containers;
+            }
+            Command::LoadFile { .. } => {
+                self.clear_containers();
+                self.set_device(data.device);
+                self.set_correction(data.correction);
+                for (id, container) in data.containers {
+                    self.insert_container(id, container);
+                }
+            }
+            Command::Render => self.draw_to_context(),
+            Command::Quit => relm::timeout(self.relm.stream(), QUIT_DELAY, || Quit),
+            Command::GetData | Command::Subscribe => (),
+        }
+        reply
+    }
+
+    /// The data for a new container, which covers the whole device
+    fn new_container_data(&self) -> PatternContainerData {
+        PatternContainerData::for_device(&self.model.device)
+    }
+
+    /// The id of the container shown in the notebook, if there is one
+    fn current_container_id(&self) -> Option<usize> {
+        let tab_id = self.container_notebook.get_property_page() as usize;
+        let mut ids = self
+            .model
+            .pattern_data_containers
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids.get(tab_id).cloned()
+    }
+
+    /// Start designing a hologram with the Gerchberg–Saxton algorithm, on another thread.
+    /// The hologram is the size of the current container, or the device if there are no containers
+    pub fn design_hologram(&mut self) {
+        if self.hologram_channel.is_some() {
+            return;
+        }
+        let (width, height) = match self
+            .current_container_id()
+            .and_then(|id| self.model.pattern_data_containers.get(&id))
+        {
+            Some(container) => (
+                (container.bottom_right.0 - container.top_left.0).max(1.0) as usize,
+                (container.bottom_right.1 - container.top_left.1).max(1.0) as usize,
+            ),
+            None => (self.model.device.width, self.model.device.height),
+        };
+        let spots_text = self
+            .hologram_spots
+            .get_text()
+            .map(|text| text.to_string())
+            .unwrap_or_default();
+        let spots = match Spot::parse_list(&spots_text) {
+            Ok(spots) => spots,
+            Err(e) => {
+                self.hologram_progress.set_text(e.as_str());
+                return;
+            }
+        };
+        let image = self.hologram_target.get_filename();
+        if spots.is_empty() && image.is_none() {
+            self.hologram_progress
+                .set_text("Choose a target image or enter some spots");
+            return;
+        }
+        let algorithm = if self.hologram_weighted.get_active() {
+            Algorithm::Weighted
+        } else {
+            Algorithm::GerchbergSaxton
+        };
+        let iterations = self.hologram_iterations.get_value_as_int().max(1) as usize;
+        let stream = self.relm.stream().clone();
+        let (channel, sender) = relm::Channel::new(move |msg| stream.emit(msg));
+        self.hologram_channel = Some(channel);
+        self.hologram_progress.set_fraction(0.0);
+        self.hologram_progress.set_text("Designing hologram");
+        std::thread::spawn(move || {
+            let target = match image {
+                Some(path) if spots.is_empty() => {
+                    Target::from_image(path, width, height).map_err(|e| e.to_string())
+                }
+                _ => Target::from_spots(&spots, width, height).map_err(|e| e.to_string()),
+            };
+            let hologram = target.map(|target| {
+                gerchberg_saxton::run(&target, algorithm, iterations, |done, total| {
+                    let _ = sender.send(HologramProgress(done, total));
+                })
+            });
+            let _ = sender.send(HologramDesigned(hologram));
+        });
+    }
+
+    /// Save a designed hologram, and add it to the current container as an image pattern.
+    /// A container is added if there aren't any
+    pub fn add_hologram(&mut self, hologram: PhaseMap) {
+        use gtk::ResponseType;
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Save hologram"),
+            Some(&self.root()),
+            gtk::FileChooserAction::Save,
+            &[
+                ("_Cancel", ResponseType::Cancel),
+                ("_Save", ResponseType::Accept),
+            ],
+        );
+        dialog.set_do_overwrite_confirmation(true);
+        dialog.set_current_name("hologram.png");
+        let filename = if ResponseType::from(dialog.run()) == ResponseType::Accept {
+            dialog.get_filename()
+        } else {
+            None
+        };
+        dialog.emit_close();
+        let filename = match filename {
+            Some(filename) => filename,
+            None => {
+                self.hologram_progress.set_text("Hologram not saved");
+                return;
+            }
+        };
+        if let Err(e) = hologram.save_image(&filename) {
+            self.hologram_progress
+                .set_text(format!("Couldn't save the hologram: {}", e).as_str());
+            return;
+        }
+        let id = match self.current_container_id() {
+            Some(id) => id,
+            None => {
+                self.add_new_container(self.new_container_data());
+                self.model.current_container_id - 1
+            }
+        };
+        if let Some(container) = self.pattern_containers.get(&id) {
+            container
+                .stream()
+                .emit(PatternContainerMsg::AddPattern(PatternKind::Image(
+                    ImageData::new(filename, hologram),
+                )));
+        }
+        self.hologram_progress.set_text("Hologram added");
+    }
+
+    /// The ids of the container and pattern of the traps in the current container
+    fn trap_pattern(&self) -> Option<(usize, usize)> {
+        let c_id = self.current_container_id()?;
+        let container = self.model.pattern_data_containers.get(&c_id)?;
+        let mut ids = container
+            .patterns
+            .iter()
+            .filter(|(_, pattern)| matches!(pattern, PatternKind::Traps(_)))
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids.first().map(|&p_id| (c_id, p_id))
+    }
+
+    /// The traps in the current container
+    fn traps(&self) -> Option<&TrapsData> {
+        let (c_id, p_id) = self.trap_pattern()?;
+        match self.model.pattern_data_containers.get(&c_id)?.patterns.get(&p_id)? {
+            PatternKind::Traps(traps) => Some(traps),
+            _ => None,
+        }
+    }
+
+    fn traps_mut(&mut self) -> Option<&mut TrapsData> {
+        let (c_id, p_id) = self.trap_pattern()?;
+        match self
+            .model
+            .pattern_data_containers
+            .get_mut(&c_id)?
+            .patterns
+            .get_mut(&p_id)?
+        {
+            PatternKind::Traps(traps) => Some(traps),
+            _ => None,
+        }
+    }
+
+    /// Design the gratings of the traps in a container on another thread, for a hologram the
+    /// size of the container. Traps which change while a design is running are designed again
+    /// once it finishes, so the designs don't queue up behind a spin button which is held down
+    fn design_traps(&mut self, c_id: usize, p_id: usize) {
+        if self.traps_channel.is_some() {
+            self.model.traps_pending.insert((c_id, p_id));
+            return;
+        }
+        let container = match self.model.pattern_data_containers.get(&c_id) {
+            Some(container) => container,
+            None => return,
+        };
+        let width = (container.bottom_right.0 - container.top_left.0).max(0.0) as usize;
+        let height = (container.bottom_right.1 - container.top_left.1).max(0.0) as usize;
+        let mut traps = match container.patterns.get(&p_id) {
+            Some(PatternKind::Traps(traps)) => traps.clone(),
+            _ => return,
+        };
+        let stream = self.relm.stream().clone();
+        let (channel, sender) = relm::Channel::new(move |msg| stream.emit(msg));
+        self.traps_channel = Some(channel);
+        std::thread::spawn(move || {
+            gerchberg_saxton::design_traps(&mut traps, width, height);
+            let _ = sender.send(TrapsDesigned(c_id, p_id, traps));
+        });
+    }
+
+    /// Use the gains and phases of designed traps, unless they have changed since the design
+    /// started, and start designing the traps which changed
+    fn traps_designed(&mut self, c_id: usize, p_id: usize, designed: TrapsData) {
+        self.traps_channel = None;
+        if !self.model.traps_pending.contains(&(c_id, p_id)) {
+            let traps = self
+                .model
+                .pattern_data_containers
+                .get_mut(&c_id)
+                .and_then(|container| container.patterns.get_mut(&p_id));
+            if let Some(PatternKind::Traps(traps)) = traps {
+                if traps.traps.len() == designed.traps.len() {
+                    for (trap, designed) in traps.traps.iter_mut().zip(designed.traps) {
+                        trap.gain = designed.gain;
+                        trap.phase = designed.phase;
+                    }
+                }
+            }
+            self.draw_to_context();
+        }
+        let next = self.model.traps_pending.iter().next().cloned();
+        if let Some((c_id, p_id)) = next {
+            self.model.traps_pending.remove(&(c_id, p_id));
+            self.design_traps(c_id, p_id);
+        }
+    }
+
+    /// Start or stop editing traps on the preview.
+    /// A traps pattern is added to the current container if it doesn't have one
+    fn set_trap_mode(&mut self, trap_mode: bool) {
+        self.model.trap_mode = trap_mode;
+        self.model.dragged_trap = None;
+        if trap_mode && self.trap_pattern().is_none() {
+            let c_id = match self.current_container_id() {
+                Some(id) => id,
+                None => {
+                    self.add_new_container(self.new_container_data());
+                    self.model.current_container_id - 1
+                }
+            };
+            let centre = self.model.pattern_data_containers.get(&c_id).map(|c| {
+                (
+                    (c.top_left.0 + c.bottom_right.0) / 2.0 - c.pos.0,
+                    (c.top_left.1 + c.bottom_right.1) / 2.0 - c.pos.1,
+                )
+            });
+            if let (Some(container), Some(centre)) = (self.pattern_containers.get(&c_id), centre) {
+                container
+                    .stream()
+                    .emit(PatternContainerMsg::AddPattern(PatternKind::Traps(TrapsData {
+                        a: 1.0,
+                        c: centre,
+                        ..Default::default()
+                    })));
+            }
+        }
+        self.draw_to_context();
+    }
+
+    /// The scale the device's image is drawn at in the preview
+    fn preview_scale(&self) -> f64 {
+        let allocation = self.drawing_area.get_allocation();
+        (allocation.width as f64 / self.model.device.width as f64)
+            .min(allocation.height as f64 / self.model.device.height as f64)
+    }
+
+    /// The grating wavenumber which moves light to the point (x, y) of the preview,
+    /// taking the preview as the Fourier plane with the zero order in the centre
+    fn preview_to_grating(&self, x: f64, y: f64) -> (f64, f64) {
+        let scale = self.preview_scale();
+        let (width, height) = (self.model.device.width as f64, self.model.device.height as f64);
+        (
+            2.0 * PI * (x / scale - width / 2.0) / width,
+            2.0 * PI * (y / scale - height / 2.0) / height,
+        )
+    }
+
+    /// The point of the preview, in device pixels, where a grating moves the light to
+    fn grating_to_device(&self, k: (f64, f64)) -> (f64, f64) {
+        let (width, height) = (self.model.device.width as f64, self.model.device.height as f64);
+        (
+            width / 2.0 + k.0 * width / (2.0 * PI),
+            height / 2.0 + k.1 * height / (2.0 * PI),
+        )
+    }
+
+    /// The trap closest to the point (x, y) of the preview, if there is one near it
+    fn nearest_trap(&self, x: f64, y: f64) -> Option<usize> {
+        let max_distance = 10.0;
+        let scale = self.preview_scale();
+        self.traps()?
+            .traps
+            .iter()
+            .enumerate()
+            .map(|(i, trap)| {
+                let (tx, ty) = self.grating_to_device(trap.k);
+                (i, (tx * scale - x).hypot(ty * scale - y))
+            })
+            .filter(|&(_, distance)| distance < max_distance)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(i, _)| i)
+    }
+
+    /// Add or start dragging a trap with the left button, and delete a trap with the right button
+    fn trap_press(&mut self, x: f64, y: f64, button: u32) {
+        if !self.model.trap_mode {
+            return;
+        }
+        let nearest = self.nearest_trap(x, y);
+        let k = self.preview_to_grating(x, y);
+        match (button, nearest) {
+            (1, Some(i)) => self.model.dragged_trap = Some(i),
+            (1, None) => {
+                if let Some(traps) = self.traps_mut() {
+                    traps.traps.push(Trap::new(k));
+                    let i = traps.traps.len() - 1;
+                    self.model.dragged_trap = Some(i);
+                }
+            }
+            (3, Some(i)) => {
+                if let Some(traps) = self.traps_mut() {
+                    traps.traps.remove(i);
+                }
+                if let Some((c_id, p_id)) = self.trap_pattern() {
+                    self.design_traps(c_id, p_id);
+                }
+            }
+            _ => (),
+        }
+        self.draw_to_context();
+    }
+
+    /// Move the trap being dragged. The traps are only redesigned when it is dropped
+    fn trap_motion(&mut self, x: f64, y: f64) {
+        if let Some(i) = self.model.dragged_trap {
+            let k = self.preview_to_grating(x, y);
+            if let Some(trap) = self.traps_mut().and_then(|traps| traps.traps.get_mut(i)) {
+                trap.k = k;
+            }
+            self.draw_to_context();
+        }
+    }
+
+    fn trap_release(&mut self) {
+        if self.model.dragged_trap.take().is_some() {
+            if let Some((c_id, p_id)) = self.trap_pattern() {
+                self.design_traps(c_id, p_id);
+            }
+            self.draw_to_context();
+        }
+    }
+
+    /// Scrolling over a trap moves it along the beam, or changes its weight with shift held
+    fn trap_scroll(&mut self, x: f64, y: f64, up: bool, shift: bool) {
+        let z_step = 0.00001;
+        let weight_step = 1.1;
+        if !self.model.trap_mode {
+            return;
+        }
+        if let Some(i) = self.nearest_trap(x, y) {
+            if let Some(trap) = self.traps_mut().and_then(|traps| traps.traps.get_mut(i)) {
+                match (shift, up) {
+                    (true, true) => trap.weight *= weight_step,
+                    (true, false) => trap.weight /= weight_step,
+                    (false, true) => trap.z += z_step,
+                    (false, false) => trap.z -= z_step,
+                }
+            }
+            if let Some((c_id, p_id)) = self.trap_pattern() {
+                self.design_traps(c_id, p_id);
+            }
+            self.draw_to_context();
+        }
+    }
+
+    /// Draw the traps over the preview. The context is scaled to device pixels
+    fn draw_traps(&self, context: &cairo::Context, scale: f64) {
+        let traps = match self.traps() {
+            Some(traps) => traps,
+            None => return,
+        };
+        let (centre_x, centre_y) = self.grating_to_device((0.0, 0.0));
+        context.set_line_width(2.0 / scale);
+        context.set_font_size(12.0 / scale);
+        context.set_source_rgb(0.0, 0.6, 1.0);
+        context.move_to(centre_x - 8.0 / scale, centre_y);
+        context.line_to(centre_x + 8.0 / scale, centre_y);
+        context.move_to(centre_x, centre_y - 8.0 / scale);
+        context.line_to(centre_x, centre_y + 8.0 / scale);
+        context.stroke();
+        for (i, trap) in traps.traps.iter().enumerate() {
+            let (x, y) = self.grating_to_device(trap.k);
+            let radius = 6.0 * trap.weight.max(0.0).sqrt().max(0.3) / scale;
+            context.set_source_rgb(1.0, 0.2, 0.2);
+            context.new_sub_path();
+            context.arc(x, y, radius, 0.0, 2.0 * PI);
+            if self.model.dragged_trap == Some(i) {
+                context.fill();
+            } else {
+                context.stroke();
+            }
+            if trap.z != 0.0 {
+                context.move_to(x + radius + 2.0 / scale, y);
+                context.show_text(&format!("z {:.5}", trap.z));
+            }
+        }
+    }
+
+    /// Draw a circle over the preview for the waist of each container's illumination,
+    /// and the edges of measured intensity images
+    fn draw_illumination(&self, context: &cairo::Context, scale: f64) {
+        context.set_line_width(2.0 / scale);
+        context.set_source_rgb(1.0, 0.8, 0.0);
+        for container in self.model.pattern_data_containers.values() {
+            let illumination = match container.illumination {
+                Some(ref illumination) => illumination,
+                None => continue,
+            };
+            let x = container.pos.0 + illumination.centre.0;
+            let y = container.pos.1 + illumination.centre.1;
+            if let Some(intensity) = illumination.intensity() {
+                let (width, height) = (intensity.width as f64, intensity.height as f64);
+                context.rectangle(x - width / 2.0, y - height / 2.0, width, height);
+            } else {
+                context.new_sub_path();
+                context.arc(x, y, illumination.waist.max(0.0), 0.0, 2.0 * PI);
+            }
+            context.stroke();
+        }
+    }
+
     /// Remove the container at position ```id``` in the ```model.pattern_containers``` vector.
     /// Displays a dialog box
     pub fn remove_container(&mut self, id: usize) {
@@ -125,15 +848,12 @@ impl SLMController {
             Some(&self.root()),
             DialogFlags::DESTROY_WITH_PARENT,
             &[
-                ("yes", gtk::ResponseType::Accept.into()),
-                ("no", gtk::ResponseType::Reject.into()),
+                ("yes", gtk::ResponseType::Accept),
+                ("no", gtk::ResponseType::Reject),
             ],
         );
         if ResponseType::from(dialog.run()) == ResponseType::Accept {
-            if let Some(widget) = self.pattern_containers.remove(&id) {
-                self.container_notebook.remove(widget.widget());
-            }
-            self.model.pattern_data_containers.remove(&id);
+            self.remove_container_widget(id);
         }
         dialog.emit_close();
     }
@@ -146,15 +866,12 @@ impl SLMController {
             Some(&self.root()),
             DialogFlags::DESTROY_WITH_PARENT,
             &[
-                ("yes", gtk::ResponseType::Accept.into()),
-                ("no", gtk::ResponseType::Reject.into()),
+                ("yes", gtk::ResponseType::Accept),
+                ("no", gtk::ResponseType::Reject),
             ],
         );
         if ResponseType::from(dialog.run()) == ResponseType::Accept {
-            for (_, widget) in self.pattern_containers.drain() {
-                self.container_notebook.remove(widget.widget());
-            }
-            self.model.pattern_data_containers.clear();
+            self.clear_containers();
         }
         dialog.emit_close();
     }
@@ -174,33 +891,92 @@ impl SLMController {
         if ResponseType::from(dialog.run()) == ResponseType::Accept {
             if let Some(filename) = dialog.get_filename() {
                 if let Ok(file) = File::create(filename) {
-                    serde_json::ser::to_writer_pretty(file, &self.model.pattern_data_containers);
+                    let _ = self.slm_data().to_writer(file);
                 }
             }
         }
         dialog.emit_close();
     }
 
-    pub fn load_file<T: std::convert::AsRef<std::path::Path>>(&mut self, p: T) {
-        if let Ok(file) = File::open(p) {
-            if let Ok(data) =
-                serde_json::de::from_reader::<_, HashMap<usize, PatternContainerData>>(file)
-            {
-                for (_, container) in data.iter() {
-                    self.add_new_container_no_increment(container.clone());
-                    if let Some(comp) = self
-                        .pattern_containers
-                        .get(&self.model.current_container_id)
-                    {
-                        for (_, pattern) in container.patterns.iter() {
-                            comp.stream()
-                                .emit(PatternContainerMsg::AddPattern(pattern.clone()));
-                        }
-                    }
-                    self.model.current_container_id += 1;
+    /// Export the rendered phase in full precision, in a format chosen in the save dialog
+    pub fn export_phase(&self) {
+        use gtk::ResponseType;
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Export phase"),
+            Some(&self.root()),
+            gtk::FileChooserAction::Save,
+            &[
+                ("_Cancel", ResponseType::Cancel),
+                ("_Save", ResponseType::Accept),
+            ],
+        );
+        dialog.set_do_overwrite_confirmation(true);
+        let options = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        let format_combo = gtk::ComboBoxText::new();
+        for name in Format::NAMES {
+            format_combo.append_text(name);
+        }
+        format_combo.set_active(0);
+        let wrapped_button = gtk::CheckButton::new_with_label("wrapped");
+        wrapped_button.set_active(true);
+        options.pack_start(&gtk::Label::new("format"), false, false, 0);
+        options.pack_start(&format_combo, false, false, 0);
+        options.pack_start(&wrapped_button, false, false, 0);
+        options.show_all();
+        dialog.set_extra_widget(&options);
+        dialog.set_current_name("phase.png");
+        if ResponseType::from(dialog.run()) == ResponseType::Accept {
+            let format = format_combo
+                .get_active_text()
+                .and_then(|name| Format::from_name(name.as_str()));
+            if let (Some(filename), Some(format)) = (dialog.get_filename(), format) {
+                if let Err(e) = export::export(
+                    &filename,
+                    format,
+                    &self.slm_data(),
+                    &self.model.calibration,
+                    wrapped_button.get_active(),
+                ) {
+                    show_error(
+                        Some(&self.root()),
+                        &format!("Couldn't export the phase to {}: {}", filename.display(), e),
+                    );
                 }
             }
         }
+        dialog.emit_close();
+    }
+
+    /// Replace the device, containers and correction with those saved in a file, as the remote
+    /// control's ```load_file``` does. Errors are shown in a dialog
+    pub fn load_file<T: std::convert::AsRef<std::path::Path>>(&mut self, p: T) {
+        let p = p.as_ref();
+        let data = File::open(p)
+            .map_err(|e| e.to_string())
+            .and_then(|file| SLMData::from_reader(file).map_err(|e| e.to_string()));
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                show_error(
+                    Some(&self.root()),
+                    &format!("Couldn't load {}: {}", p.display(), e),
+                );
+                return;
+            }
+        };
+        let errors = data.image_errors();
+        if !errors.is_empty() {
+            show_error(
+                Some(&self.root()),
+                &format!("Some images couldn't be read:\n{}", errors.join("\n")),
+            );
+        }
+        self.clear_containers();
+        self.set_device(data.device);
+        self.set_correction(data.correction);
+        for (id, container) in data.containers {
+            self.insert_container(id, container);
+        }
     }
 
     pub fn load_containers(&mut self) {
@@ -222,49 +998,189 @@ impl SLMController {
         dialog.emit_close();
     }
 
+    /// Change the device the patterns are displayed on
+    pub fn set_device(&mut self, device: DeviceProfile) {
+        self.model.image_buffer = new_image_buffer(&device);
+        self.model.calibration = Arc::new(load_calibration(&device));
+        self.widget.set_title(&format!("Phase - {}", device.name));
+        self.model.device = device;
+    }
+
+    /// Replace the aberration correction, and the controls for it
+    pub fn set_correction(&mut self, mut correction: ZernikeCorrection) {
+        let num_coefficients = correction.coefficients.len().max(zernike::NUM_POLYNOMIALS);
+        correction.coefficients.resize(num_coefficients, 0.0);
+        if let Some(child) = self.zernike_window.get_child() {
+            self.zernike_window.remove(&child);
+        }
+        self.zernike_controller = self
+            .zernike_window
+            .add_widget::<ZernikeController>((correction.clone(), self.relm.clone()));
+        self.model.correction = correction;
+    }
+
+    /// All of the data needed to save or render the current patterns
+    pub fn slm_data(&self) -> SLMData {
+        SLMData {
+            device: self.model.device.clone(),
+            containers: self.model.pattern_data_containers.clone(),
+            correction: self.model.correction.clone(),
+        }
+    }
+
+    /// The rendered phase with the containers which light it, for the simulation
+    fn rendered_phase(&self, phase: Vec<f64>) -> RenderedPhase {
+        let containers = self
+            .model
+            .pattern_data_containers
+            .iter()
+            .filter(|(_, container)| container.illumination.is_some())
+            .map(|(id, container)| (*id, container.clone()))
+            .collect();
+        let wavelength = self
+            .current_container_id()
+            .and_then(|id| self.model.pattern_data_containers.get(&id))
+            .and_then(|container| container.illumination.as_ref())
+            .and_then(|illumination| illumination.wavelength);
+        RenderedPhase {
+            phase,
+            device: self.model.device.clone(),
+            containers,
+            wavelength,
+        }
+    }
+
+    /// Paint the image buffer into the drawing area, with the illumination and traps over it
+    fn paint_preview(&mut self) {
+        let scale = self.preview_scale();
+        let context = self.draw_handler.get_context();
+        context.set_source_rgb(0.0, 0.0, 0.0);
+        context.paint();
+        context.scale(scale, scale);
+        context.set_source_pixbuf(&self.model.image_buffer, 0.0, 0.0);
+        context.paint();
+        self.draw_illumination(&context, scale);
+        if self.model.trap_mode {
+            self.draw_traps(&context, scale);
+        }
+    }
+
+    /// Render the pattern containers on another thread, and paint them into the drawing area
+    /// when they are rendered. Changes made while a render is running are rendered together
+    /// once it finishes, so the renders don't queue up behind a spin button which is held down
     pub fn draw_to_context(&mut self) {
-        // let context = self.draw_handler.get_context();
-        // context.set_source_pixbuf(self.model.image_buffer, 0f64, 0f64);
+        self.paint_preview();
+        if self.render_channel.is_some() {
+            self.model.render_pending = true;
+            return;
+        }
+        self.model.render_pending = false;
+        let data = self.slm_data();
+        let calibration = self.model.calibration.clone();
+        let stream = self.relm.stream().clone();
+        let (channel, sender) = relm::Channel::new(move |msg| stream.emit(msg));
+        self.render_channel = Some(channel);
+        std::thread::spawn(move || {
+            let (width, height) = (data.device.width, data.device.height);
+            let phase = render::render_device_phase(&data, &calibration);
+            let grey = render::device_grey(&data, &calibration, &phase);
+            let _ = sender.send(PatternRendered(width, height, phase, grey));
+        });
+    }
+
+    /// Show a render in the preview and on the SLM, and start the next render if the patterns
+    /// have changed since it started
+    fn pattern_rendered(&mut self, width: usize, height: usize, phase: Vec<f64>, grey: Vec<u8>) {
+        self.render_channel = None;
+        // the device may have changed while it was rendering
+        if (width, height) == (self.model.device.width, self.model.device.height) {
+            self.model.image_buffer = grey_to_pixbuf(&grey, width as i32, height as i32);
+            self.paint_preview();
+            // the frames of a playing sequence are shown on the SLM instead
+            if !self.model.sequence_playing {
+                self.output_window
+                    .stream()
+                    .emit(OutputWindowMsg::SetImage(self.model.image_buffer.clone()));
+            }
+            let rendered = self.rendered_phase(phase);
+            self.simulation
+                .stream()
+                .emit(SimulationControllerMsg::SetPhase(rendered));
+        } else {
+            self.model.render_pending = true;
+        }
+        if self.model.render_pending {
+            self.draw_to_context();
+        }
+    }
+
+    /// Send the patterns in the editor to the sequence, for the action which asked for them
+    fn send_sequence_editor(&self, action: EditorAction) {
+        let editor = Editor {
+            data: self.slm_data(),
+            current_container: self.current_container_id(),
+            calibration: self.model.calibration.clone(),
+        };
+        self.sequence
+            .stream()
+            .emit(SequenceControllerMsg::Editor(action, Box::new(editor)));
+    }
+
+    /// Show the grey levels of a frame of the sequence on the SLM and in the preview
+    fn show_sequence_frame(&mut self, grey: &[u8]) {
+        let pixbuf = grey_to_pixbuf(
+            grey,
+            self.model.device.width as i32,
+            self.model.device.height as i32,
+        );
+        self.output_window
+            .stream()
+            .emit(OutputWindowMsg::SetImage(pixbuf.clone()));
+        let scale = self.preview_scale();
+        let context = self.draw_handler.get_context();
+        context.set_source_rgb(0.0, 0.0, 0.0);
+        context.paint();
+        context.scale(scale, scale);
+        context.set_source_pixbuf(&pixbuf, 0.0, 0.0);
+        context.paint();
     }
 }
 
 impl Update for SLMController {
     type Model = SLMControllerModel;
-    type ModelParam = ();
+    /// The device, and the address to listen for remote control on
+    type ModelParam = (DeviceProfile, Option<String>);
     type Msg = SLMControllerMsg;
 
-    fn model(_: &Relm<Self>, _: Self::ModelParam) -> Self::Model {
+    fn model(_: &Relm<Self>, (device, remote_address): Self::ModelParam) -> Self::Model {
         SLMControllerModel {
             pattern_data_containers: HashMap::new(),
             current_container_id: 0,
-            image_buffer: gdk_pixbuf::Pixbuf::new(gdk_pixbuf::Colorspace::Rgb, false, 8, 1920, 1080).unwrap(),
+            image_buffer: new_image_buffer(&device),
+            calibration: Arc::new(load_calibration(&device)),
+            correction: ZernikeCorrection::for_device(&device),
+            device,
+            trap_mode: false,
+            dragged_trap: None,
+            traps_pending: HashSet::new(),
+            render_pending: false,
+            remote_address,
+            sequence_playing: false,
         }
     }
 
     fn update(&mut self, event: Self::Msg) {
         match event {
             Quit => gtk::main_quit(),
-            AddTab => self.add_new_container(PatternContainerData {
-                top_left: (0.0, 0.0),
-                bottom_right: (1920.0, 1080.0),
-                scale: (1.0, 1.0),
-                ..Default::default()
-            }),
+            AddTab => self.add_new_container(self.new_container_data()),
             RemoveTab => {
-                let tab_id = self.container_notebook.get_property_page() as usize;
-                let mut ids = self
-                    .model
-                    .pattern_data_containers
-                    .iter()
-                    .map(|(x, _)| x)
-                    .collect::<Vec<_>>();
-                ids.sort();
-                if tab_id < ids.len() {
-                    self.remove_container(*ids[tab_id]);
+                if let Some(id) = self.current_container_id() {
+                    self.remove_container(id);
                 }
             }
             RemoveAllTabs => self.remove_all_containers(),
             SaveContainers => self.save_containers(),
+            ExportPhase => self.export_phase(),
             LoadContainers => self.load_containers(),
             RemoveController(c_id, p_id) => {
                 if let Some(container) = self.model.pattern_data_containers.get_mut(&c_id) {
@@ -276,22 +1192,17 @@ impl Update for SLMController {
                     container.patterns.insert(p_id, data);
                 }
             }
-            UpdatePatternL(c_id, p_id, x) => update_from_pattern_spinner!(self, c_id, p_id, x, l),
-            UpdatePatternA(c_id, p_id, x) => update_from_pattern_spinner!(self, c_id, p_id, x, a),
-            UpdatePatternKx(c_id, p_id, x) => {
-                update_from_pattern_spinner!(self, c_id, p_id, x, k, 0)
-            }
-            UpdatePatternKy(c_id, p_id, x) => {
-                update_from_pattern_spinner!(self, c_id, p_id, x, k, 1)
-            }
-            UpdatePatternCx(c_id, p_id, x) => {
-                update_from_pattern_spinner!(self, c_id, p_id, x, c, 0)
-            }
-            UpdatePatternCy(c_id, p_id, x) => {
-                update_from_pattern_spinner!(self, c_id, p_id, x, c, 1)
-            }
-            UpdatePatternPhase(c_id, p_id, x) => {
-                update_from_pattern_spinner!(self, c_id, p_id, x, phase)
+            UpdatePatternParameter(c_id, p_id, name, x) => {
+                let mut traps = false;
+                if let Some(container) = self.model.pattern_data_containers.get_mut(&c_id) {
+                    if let Some(pattern) = container.patterns.get_mut(&p_id) {
+                        pattern.set_parameter(&name, x);
+                        traps = matches!(pattern, PatternKind::Traps(_));
+                    }
+                }
+                if traps {
+                    self.design_traps(c_id, p_id);
+                }
             }
             UpdateContainerCx(c_id, x) => update_from_container_spinner!(self, c_id, x, pos, 0),
             UpdateContainerCy(c_id, x) => update_from_container_spinner!(self, c_id, x, pos, 1),
@@ -313,7 +1224,70 @@ impl Update for SLMController {
             UpdateContainerBRY(c_id, x) => {
                 update_from_container_spinner!(self, c_id, x, bottom_right, 1)
             }
+            UpdateContainerCombination(c_id, mode) => {
+                update_from_container_spinner!(self, c_id, mode, combination)
+            }
+            UpdateContainerIllumination(c_id, illumination) => {
+                update_from_container_spinner!(self, c_id, illumination, illumination)
+            }
             RenderPattern => self.draw_to_context(),
+            PatternRendered(width, height, phase, grey) => {
+                self.pattern_rendered(width, height, phase, grey)
+            }
+            UpdateZernikeCentreX(x) => {
+                self.model.correction.centre.0 = x;
+                self.draw_to_context();
+            }
+            UpdateZernikeCentreY(x) => {
+                self.model.correction.centre.1 = x;
+                self.draw_to_context();
+            }
+            UpdateZernikeRadius(x) => {
+                self.model.correction.radius = x;
+                self.draw_to_context();
+            }
+            UpdateZernikeCoefficient(i, x) => {
+                if let Some(coefficient) = self.model.correction.coefficients.get_mut(i) {
+                    *coefficient = x;
+                }
+                self.draw_to_context();
+            }
+            SelectOutputMonitor(monitor) => self
+                .output_window
+                .stream()
+                .emit(OutputWindowMsg::SetMonitor(monitor)),
+            ShowOutputWindow(visible) => self
+                .output_window
+                .stream()
+                .emit(OutputWindowMsg::SetVisible(visible)),
+            DesignHologram => self.design_hologram(),
+            HologramProgress(done, total) => self
+                .hologram_progress
+                .set_fraction(done as f64 / total.max(1) as f64),
+            HologramDesigned(hologram) => {
+                self.hologram_channel = None;
+                match hologram {
+                    Ok(hologram) => self.add_hologram(hologram),
+                    Err(e) => self.hologram_progress.set_text(e.as_str()),
+                }
+            }
+            SetTrapMode(trap_mode) => self.set_trap_mode(trap_mode),
+            TrapPress(x, y, button) => self.trap_press(x, y, button),
+            TrapMotion(x, y) => self.trap_motion(x, y),
+            TrapRelease => self.trap_release(),
+            TrapScroll(x, y, up, shift) => self.trap_scroll(x, y, up, shift),
+            TrapsDesigned(c_id, p_id, traps) => self.traps_designed(c_id, p_id, traps),
+            Remote(command, sender) => {
+                let reply = self.handle_remote(command);
+                let _ = sender.send(reply);
+            }
+            SequenceEditor(action) => self.send_sequence_editor(action),
+            ShowSequenceFrame(grey) => self.show_sequence_frame(&grey),
+            SequencePlaying(playing) => self.model.sequence_playing = playing,
+            FramePresented => self
+                .sequence
+                .stream()
+                .emit(SequenceControllerMsg::FramePresented),
         }
     }
 }
@@ -327,20 +1301,79 @@ impl Widget for SLMController {
 
     fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
         let widget = gtk::Window::new(gtk::WindowType::Toplevel);
+        widget.set_title(&format!("Phase - {}", model.device.name));
         let image_control_split = gtk::Box::new(gtk::Orientation::Horizontal, 0);
         let drawing_area = gtk::DrawingArea::new();
         let mut draw_handler = DrawHandler::new().unwrap();
         draw_handler.init(&drawing_area);
+        drawing_area.add_events(
+            gdk::EventMask::BUTTON_PRESS_MASK
+                | gdk::EventMask::BUTTON_RELEASE_MASK
+                | gdk::EventMask::BUTTON_MOTION_MASK
+                | gdk::EventMask::SCROLL_MASK,
+        );
+        let simulation_area = gtk::DrawingArea::new();
         let split_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        let zernike_expander = gtk::Expander::new("Aberration correction");
+        let zernike_window =
+            gtk::ScrolledWindow::new::<gtk::Adjustment, _, gtk::Adjustment, _>(None, None);
+        zernike_window.set_min_content_height(200);
+        let zernike_controller = zernike_window
+            .add_widget::<ZernikeController>((model.correction.clone(), relm.clone()));
+        zernike_expander.add(&zernike_window);
+        let hologram_expander = gtk::Expander::new("Design hologram");
+        let hologram_grid = gtk::Grid::new();
+        hologram_grid.set_column_spacing(5);
+        let hologram_target =
+            gtk::FileChooserButton::new("Target image", gtk::FileChooserAction::Open);
+        let hologram_spots = gtk::Entry::new();
+        hologram_spots.set_placeholder_text("x, y, weight; x, y, weight; ...");
+        let hologram_iterations_adjustment =
+            gtk::Adjustment::new(30.0, 1.0, 10000.0, 1.0, 0.0, 0.0);
+        let hologram_iterations = gtk::SpinButton::new(&hologram_iterations_adjustment, 0.0, 0);
+        let hologram_weighted = gtk::CheckButton::new_with_label("weighted");
+        let hologram_button = gtk::Button::new_with_label("Design");
+        let hologram_progress = gtk::ProgressBar::new();
+        hologram_progress.set_show_text(true);
+        hologram_progress.set_text("");
+        hologram_grid.attach(&gtk::Label::new("target image"), 0, 0, 1, 1);
+        hologram_grid.attach(&hologram_target, 1, 0, 2, 1);
+        hologram_grid.attach(&gtk::Label::new("or spots"), 0, 1, 1, 1);
+        hologram_grid.attach(&hologram_spots, 1, 1, 2, 1);
+        hologram_grid.attach(&gtk::Label::new("iterations"), 0, 2, 1, 1);
+        hologram_grid.attach(&hologram_iterations, 1, 2, 1, 1);
+        hologram_grid.attach(&hologram_weighted, 2, 2, 1, 1);
+        hologram_grid.attach(&hologram_button, 0, 3, 1, 1);
+        hologram_grid.attach(&hologram_progress, 1, 3, 2, 1);
+        hologram_expander.add(&hologram_grid);
+        let simulation_expander = gtk::Expander::new("Simulation");
+        let simulation = simulation_expander.add_widget::<SimulationController>((
+            simulation_area.clone(),
+            model.device.clone(),
+        ));
+        let sequence_expander = gtk::Expander::new("Sequence");
+        let remote_events = Events::default();
+        let sequence = sequence_expander
+            .add_widget::<SequenceController>((remote_events.clone(), relm.clone()));
         let container_control_box = gtk::Box::new(gtk::Orientation::Horizontal, 0);
         let container_notebook = gtk::Notebook::new();
         container_notebook.set_scrollable(true);
         let add_button = gtk::Button::new_with_label("Add container");
         let save_button = gtk::Button::new_with_label("Save containers");
+        let export_button = gtk::Button::new_with_label("Export phase");
         let load_button = gtk::Button::new_with_label("Load containers");
         let delete_button = gtk::Button::new_with_label("Delete current container");
         let delete_all_button = gtk::Button::new_with_label("Delete all containers");
         let update_button = gtk::Button::new_with_label("Update pattern");
+        let trap_button = gtk::ToggleButton::new_with_label("Edit traps");
+        let output_control_box = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        let monitor_combo = gtk::ComboBoxText::new();
+        for name in output_window::monitor_names() {
+            monitor_combo.append_text(&name);
+        }
+        monitor_combo.set_active(0);
+        let output_button = gtk::ToggleButton::new_with_label("Show output");
+        let output_window = relm::create_component::<OutputWindow>(());
         connect!(
             relm,
             widget,
@@ -349,31 +1382,123 @@ impl Widget for SLMController {
         );
         connect!(relm, add_button, connect_clicked(_), AddTab);
         connect!(relm, save_button, connect_clicked(_), SaveContainers);
+        connect!(relm, export_button, connect_clicked(_), ExportPhase);
         connect!(relm, load_button, connect_clicked(_), LoadContainers);
         connect!(relm, delete_button, connect_clicked(_), RemoveTab);
         connect!(relm, delete_all_button, connect_clicked(_), RemoveAllTabs);
         connect!(relm, update_button, connect_clicked(_), RenderPattern);
+        connect!(relm, hologram_button, connect_clicked(_), DesignHologram);
+        connect!(
+            relm,
+            trap_button,
+            connect_toggled(x),
+            SetTrapMode(x.get_active())
+        );
+        connect!(
+            relm,
+            drawing_area,
+            connect_button_press_event(_, event),
+            return (
+                TrapPress(event.get_position().0, event.get_position().1, event.get_button()),
+                gtk::Inhibit(false)
+            )
+        );
+        connect!(
+            relm,
+            drawing_area,
+            connect_motion_notify_event(_, event),
+            return (
+                TrapMotion(event.get_position().0, event.get_position().1),
+                gtk::Inhibit(false)
+            )
+        );
+        connect!(
+            relm,
+            drawing_area,
+            connect_button_release_event(_, _),
+            return (TrapRelease, gtk::Inhibit(false))
+        );
+        connect!(
+            relm,
+            drawing_area,
+            connect_scroll_event(_, event),
+            return (
+                TrapScroll(
+                    event.get_position().0,
+                    event.get_position().1,
+                    event.get_direction() == gdk::ScrollDirection::Up,
+                    event.get_state().contains(gdk::ModifierType::SHIFT_MASK)
+                ),
+                gtk::Inhibit(false)
+            )
+        );
+        connect!(output_window@OutputWindowMsg::Presented, relm, FramePresented);
+        connect!(
+            relm,
+            monitor_combo,
+            connect_changed(x),
+            SelectOutputMonitor(x.get_active().unwrap_or(0) as i32)
+        );
+        connect!(
+            relm,
+            output_button,
+            connect_toggled(x),
+            ShowOutputWindow(x.get_active())
+        );
 
         container_control_box.pack_start(&add_button, false, false, 0);
         container_control_box.pack_start(&save_button, false, false, 0);
+        container_control_box.pack_start(&export_button, false, false, 0);
         container_control_box.pack_start(&load_button, false, false, 0);
+        container_control_box.pack_start(&trap_button, false, false, 0);
         container_control_box.pack_end(&delete_all_button, false, false, 0);
         container_control_box.pack_end(&delete_button, false, false, 0);
         split_box.pack_start(&container_control_box, false, false, 0);
+        split_box.pack_start(&zernike_expander, false, false, 0);
+        split_box.pack_start(&hologram_expander, false, false, 0);
+        split_box.pack_start(&simulation_expander, false, false, 0);
+        split_box.pack_start(&sequence_expander, false, false, 0);
         split_box.pack_start(&container_notebook, true, true, 0);
+        output_control_box.pack_start(&gtk::Label::new("output monitor"), false, false, 0);
+        output_control_box.pack_start(&monitor_combo, false, false, 0);
+        output_control_box.pack_end(&output_button, false, false, 0);
         split_box.pack_end(&update_button, false, false, 0);
+        split_box.pack_end(&output_control_box, false, false, 0);
         image_control_split.pack_start(&drawing_area, true, true, 0);
+        image_control_split.pack_start(&simulation_area, true, true, 0);
         image_control_split.pack_end(&split_box, true, true, 0);
         widget.add(&image_control_split);
         widget.show_all();
+        simulation_area.hide();
 
-        SLMController {
+        let remote_address = model.remote_address.clone();
+        let mut controller = SLMController {
             model,
             widget,
-            container_notebook: container_notebook,
+            container_notebook,
             relm: relm.clone(),
             pattern_containers: HashMap::new(),
-            draw_handler: draw_handler
+            draw_handler,
+            drawing_area,
+            simulation,
+            sequence,
+            output_window,
+            zernike_window,
+            zernike_controller,
+            hologram_target,
+            hologram_spots,
+            hologram_iterations,
+            hologram_weighted,
+            hologram_progress,
+            render_channel: None,
+            hologram_channel: None,
+            traps_channel: None,
+            remote_channel: None,
+            remote_events,
+        };
+        if let Some(address) = remote_address {
+            controller.start_remote(&address);
         }
+        controller
     }
 }
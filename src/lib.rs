@@ -0,0 +1,27 @@
+//! The data model and rendering engine for SLM phase patterns.
+//! Nothing in here depends on gtk, so patterns can be rendered from scripts and tests
+//! without a display server.
+
+extern crate image;
+extern crate num_complex;
+extern crate rustfft;
+extern crate serde;
+extern crate serde_json;
+
+pub mod device;
+pub mod export;
+pub mod fourier;
+pub mod frame_sync;
+pub mod gerchberg_saxton;
+pub mod lut;
+pub mod modes;
+pub mod npy;
+pub mod pattern;
+pub mod phase_map;
+pub mod propagation;
+pub mod remote;
+pub mod render;
+pub mod sequence;
+pub mod simulation;
+pub mod slm_data;
+pub mod zernike;
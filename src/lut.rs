@@ -0,0 +1,126 @@
+//! This module contains the lookup tables which map phase onto the grey levels of an SLM.
+//! Real SLMs don't have a linear phase response, so each device is calibrated with a table
+//! giving the grey level for equally spaced phases in [0, 2π)
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use crate::render::wrap_phase;
+
+/// A lookup table from phase to grey level
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+    /// The grey level for the phase 2π i / n, where n is the number of levels
+    levels: Vec<u16>,
+}
+
+impl Lut {
+    /// Create a lookup table from the grey levels of equally spaced phases in [0, 2π).
+    /// Returns ```None``` if there are no levels
+    pub fn new(levels: Vec<u16>) -> Option<Lut> {
+        if levels.is_empty() {
+            None
+        } else {
+            Some(Lut { levels })
+        }
+    }
+
+    /// Read a lookup table.
+    /// Each line holds either a single grey level, or an index followed by a grey level,
+    /// separated by commas or whitespace. This covers csv files and the usual 256 or 1024
+    /// entry ```.lut``` files. Empty lines, lines starting with ```#```, and a header line
+    /// which isn't a number are skipped
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Lut> {
+        let mut levels = vec![];
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let value = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .rfind(|field| !field.is_empty())
+                .unwrap_or("");
+            match value.parse::<f64>() {
+                Ok(level) if level >= 0.0 && level <= f64::from(u16::MAX) => {
+                    levels.push(level.round() as u16)
+                }
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("grey level out of range on line {}", number + 1),
+                    ))
+                }
+                Err(_) if levels.is_empty() => continue,
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("couldn't read a grey level on line {}", number + 1),
+                    ))
+                }
+            }
+        }
+        Lut::new(levels).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "the lookup table is empty")
+        })
+    }
+
+    /// Load a lookup table from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Lut> {
+        Lut::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// The number of entries in the table
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Whether the table has no entries. This is never true for a constructed table
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    /// Convert a phase into the grey level given by the table
+    pub fn phase_to_level(&self, phase: f64) -> u16 {
+        let n = self.levels.len();
+        let index = (wrap_phase(phase) / (2.0 * PI) * n as f64) as usize;
+        self.levels[index.min(n - 1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_one_level_per_line() {
+        let lut = Lut::from_reader("0\n64\n128\n192\n".as_bytes()).unwrap();
+        assert_eq!(lut, Lut::new(vec![0, 64, 128, 192]).unwrap());
+    }
+
+    #[test]
+    fn reads_csv_with_a_header_and_comments() {
+        let text = "# measured at 633nm\nindex,level\n0, 10\n\n1, 20.4\n2\t31\n";
+        let lut = Lut::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(lut, Lut::new(vec![10, 20, 31]).unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_tables() {
+        for text in &["", "# only a comment\n", "0\n70000\n", "0\n-1\n", "0\n1\nlevel\n"] {
+            let error = Lut::from_reader(text.as_bytes()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn maps_phase_onto_the_entries() {
+        let lut = Lut::new(vec![0, 100, 200, 300]).unwrap();
+        assert_eq!(lut.phase_to_level(0.0), 0);
+        assert_eq!(lut.phase_to_level(PI), 200);
+        assert_eq!(lut.phase_to_level(2.0 * PI - 1e-9), 300);
+        assert_eq!(lut.phase_to_level(2.0 * PI), 0);
+    }
+}
@@ -3,6 +3,8 @@
 extern crate gtk;
 extern crate gdk;
 extern crate gdk_pixbuf;
+extern crate cairo;
+extern crate clap;
 #[macro_use]
 extern crate relm;
 #[macro_use]
@@ -10,15 +12,88 @@ extern crate relm_derive;
 extern crate serde;
 extern crate serde_json;
 
+pub mod daemon;
 pub mod gui;
+pub mod output_window;
 pub mod pattern_container;
 pub mod pattern_controller;
-pub mod slm_data;
+pub mod sequence_controller;
+pub mod simulation_controller;
+pub mod zernike_controller;
 
+use clap::{App, Arg};
 use relm::Widget;
+use std::fs::File;
+use std::net::TcpListener;
+use std::process;
 
+use daemon::Daemon;
 use gui::SLMController;
+use phase::slm_data::SLMData;
 
-fn main() -> Result<(), ()> {
-    Ok(SLMController::run(())?)
+/// The address the daemon listens for remote control on, if none is given
+const DEFAULT_REMOTE_ADDRESS: &str = "127.0.0.1:7878";
+
+fn run() -> Result<(), String> {
+    let matches = App::new("phase")
+        .about("Generate phase patterns for SLMs")
+        .arg(
+            Arg::with_name("remote")
+                .long("remote")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .help("listen for remote control commands on the address, such as 127.0.0.1:7878"),
+        )
+        .arg(
+            Arg::with_name("daemon")
+                .long("daemon")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("show the containers saved in the file on the SLM without the editor, and change them over the remote control"),
+        )
+        .arg(
+            Arg::with_name("monitor")
+                .long("monitor")
+                .takes_value(true)
+                .default_value("0")
+                .help("the monitor the daemon shows the patterns on"),
+        )
+        .get_matches();
+    let remote = matches.value_of("remote").map(str::to_string);
+    if let Some(path) = matches.value_of("daemon") {
+        let data = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| SLMData::from_reader(file).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Couldn't load {}: {}", path, e))?;
+        for error in data.image_errors() {
+            eprintln!("Couldn't load {}: {}", path, error);
+        }
+        let monitor = matches
+            .value_of("monitor")
+            .unwrap()
+            .parse::<i32>()
+            .map_err(|e| format!("Couldn't read the monitor: {}", e))?;
+        let address = remote.unwrap_or_else(|| DEFAULT_REMOTE_ADDRESS.to_string());
+        // the daemon can't be controlled without the remote control
+        let listener = TcpListener::bind(&address)
+            .and_then(|listener| listener.local_addr().map(|local| (listener, local)))
+            .map(|(listener, local)| {
+                println!("Listening for remote control on {}", local);
+                listener
+            })
+            .map_err(|e| format!("Couldn't start the remote control on {}: {}", address, e))?;
+        gtk::init().map_err(|e| format!("Couldn't start gtk: {}", e))?;
+        return Daemon::run((data, monitor, listener))
+            .map_err(|_| String::from("Couldn't start the daemon"));
+    }
+    gtk::init().map_err(|e| format!("Couldn't start gtk: {}", e))?;
+    let device = gui::choose_device_profile();
+    SLMController::run((device, remote)).map_err(|_| String::from("Couldn't start the editor"))
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("phase: {}", e);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,149 @@
+//! This module contains the Laguerre–Gaussian and Hermite–Gaussian modes,
+//! and the encoding of their amplitude into a phase only hologram.
+//! The modes are written in terms of the scaled coordinate √2·r/w, where w is the waist,
+//! and are not normalised, since only their shape matters for the hologram
+use crate::render::wrap_phase;
+
+/// The number of points sampled when finding the peak of a mode
+const PEAK_SAMPLES: usize = 2000;
+
+/// The generalised Laguerre polynomial L_p^alpha(x)
+pub fn laguerre(p: u32, alpha: f64, x: f64) -> f64 {
+    let (mut previous, mut current) = (1.0, 1.0 + alpha - x);
+    if p == 0 {
+        return previous;
+    }
+    for k in 1..p {
+        let k = f64::from(k);
+        let next = ((2.0 * k + 1.0 + alpha - x) * current - (k + alpha) * previous) / (k + 1.0);
+        previous = current;
+        current = next;
+    }
+    current
+}
+
+/// The (physicists') Hermite polynomial H_n(x)
+pub fn hermite(n: u32, x: f64) -> f64 {
+    let (mut previous, mut current) = (1.0, 2.0 * x);
+    if n == 0 {
+        return previous;
+    }
+    for k in 1..n {
+        let next = 2.0 * x * current - 2.0 * f64::from(k) * previous;
+        previous = current;
+        current = next;
+    }
+    current
+}
+
+/// The radial part of the LG_pl mode at the scaled radius rho.
+/// The sign is kept, so the mode has a phase of π where it is negative
+pub fn laguerre_gauss(p: u32, l: i32, rho: f64) -> f64 {
+    let abs_l = l.unsigned_abs();
+    rho.powi(abs_l as i32) * laguerre(p, f64::from(abs_l), rho * rho) * (-rho * rho / 2.0).exp()
+}
+
+/// One axis of the HG_mn mode at the scaled coordinate x.
+/// The mode is the product of the two axes
+pub fn hermite_gauss(n: u32, x: f64) -> f64 {
+    hermite(n, x) * (-x * x / 2.0).exp()
+}
+
+/// The largest absolute value of f on [0, end], found by sampling
+fn peak<F: Fn(f64) -> f64>(f: F, end: f64) -> f64 {
+    (0..=PEAK_SAMPLES)
+        .map(|i| f(end * i as f64 / PEAK_SAMPLES as f64).abs())
+        .fold(0.0, f64::max)
+}
+
+/// The largest absolute value of the radial part of the LG_pl mode
+pub fn laguerre_gauss_peak(p: u32, l: i32) -> f64 {
+    let end = (f64::from(2 * p) + f64::from(l.unsigned_abs()) + 1.0).sqrt() + 3.0;
+    peak(|rho| laguerre_gauss(p, l, rho), end)
+}
+
+/// The largest absolute value of one axis of the HG_mn mode
+pub fn hermite_gauss_peak(n: u32) -> f64 {
+    let end = (f64::from(2 * n) + 1.0).sqrt() + 3.0;
+    peak(|x| hermite_gauss(n, x), end)
+}
+
+/// The amplitude to encode so the light leaving the SLM has the amplitude ```amplitude```,
+/// when the SLM is lit with the amplitude ```illumination``` (both between 0 and 1).
+/// Where the beam is too dim to make the amplitude, the full beam is used
+pub fn compensate_illumination(amplitude: f64, illumination: f64) -> f64 {
+    if illumination > 0.0 {
+        (amplitude / illumination).min(1.0)
+    } else {
+        1.0
+    }
+}
+
+/// Encode a field with the given amplitude (between 0 and 1) and phase into a single phase,
+/// following Davis et al. (Applied Optics 38, 5004, 1999).
+/// The wrapped phase is scaled by the amplitude, so when a grating is included in the phase
+/// the field appears in the first diffraction order with the amplitude it was given
+pub fn encode_amplitude(amplitude: f64, phase: f64) -> f64 {
+    amplitude.clamp(0.0, 1.0) * wrap_phase(phase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9 * (1.0 + b.abs())
+    }
+
+    #[test]
+    fn laguerre_polynomials_match_their_closed_forms() {
+        for &alpha in &[0.0, 1.0, 2.5] {
+            for &x in &[0.0, 0.3, 1.7, 4.0] {
+                assert!(close(laguerre(0, alpha, x), 1.0));
+                assert!(close(laguerre(1, alpha, x), 1.0 + alpha - x));
+                let l2 = (x * x - 2.0 * (alpha + 2.0) * x + (alpha + 1.0) * (alpha + 2.0)) / 2.0;
+                assert!(close(laguerre(2, alpha, x), l2));
+            }
+        }
+    }
+
+    #[test]
+    fn hermite_polynomials_match_their_closed_forms() {
+        for &x in &[-1.5, 0.0, 0.3, 2.0] {
+            assert!(close(hermite(0, x), 1.0));
+            assert!(close(hermite(1, x), 2.0 * x));
+            assert!(close(hermite(2, x), 4.0 * x * x - 2.0));
+            assert!(close(hermite(3, x), 8.0 * x * x * x - 12.0 * x));
+        }
+    }
+
+    #[test]
+    fn peaks_of_the_lowest_modes() {
+        assert!(close(hermite_gauss_peak(0), 1.0));
+        assert!(close(laguerre_gauss_peak(0, 0), 1.0));
+        // ρ exp(-ρ²/2) peaks at ρ = 1
+        assert!((laguerre_gauss_peak(0, 1) - (-0.5f64).exp()).abs() < 1e-5);
+        assert!((laguerre_gauss_peak(0, -1) - (-0.5f64).exp()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn compensation_divides_by_the_illumination() {
+        assert!(close(compensate_illumination(0.25, 0.5), 0.5));
+        assert!(close(compensate_illumination(0.5, 0.5), 1.0));
+        // the beam is too dim, so all of it is used
+        assert!(close(compensate_illumination(0.8, 0.5), 1.0));
+        assert!(close(compensate_illumination(0.3, 0.0), 1.0));
+    }
+
+    #[test]
+    fn encoding_scales_the_wrapped_phase_by_the_amplitude() {
+        assert!(close(encode_amplitude(0.5, 3.0 * PI), 0.5 * PI));
+        assert!(close(encode_amplitude(1.0, -PI / 2.0), 1.5 * PI));
+        // the amplitude is clamped to [0, 1]
+        assert!(close(encode_amplitude(2.0, 1.0), 1.0));
+        assert!(close(encode_amplitude(-1.0, 1.0), 0.0));
+        // no amplitude gives no phase
+        assert_eq!(encode_amplitude(0.0, 2.5), 0.0);
+    }
+}
@@ -0,0 +1,336 @@
+//! This module reads and writes numpy ```.npy``` arrays, so phase maps computed in python can be
+//! displayed, and rendered phases analysed.
+//! Arrays of booleans, integers and floats of either byte order are read, in C or fortran order.
+//! Float arrays are written in little endian C order
+use std::io::{self, Read, Write};
+
+/// The type of the elements of an array
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Element {
+    Bool,
+    /// A signed (true) or unsigned (false) integer with the given number of bytes
+    Integer(bool, usize),
+    /// A float with the given number of bytes
+    Float(usize),
+}
+
+/// An array read from a ```.npy``` file, with its elements converted to f64 in C (row major) order
+#[derive(Clone, Debug, PartialEq)]
+pub struct Array {
+    pub element: Element,
+    pub shape: Vec<usize>,
+    pub data: Vec<f64>,
+}
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// The text of the value of ```key``` in the header's python dictionary
+fn header_value<'a>(header: &'a str, key: &str) -> io::Result<&'a str> {
+    let start = header
+        .find(&format!("'{}'", key))
+        .ok_or_else(|| invalid(format!("the npy header has no {}", key)))?;
+    let value = header[start + key.len() + 2..].trim_start();
+    Ok(value.strip_prefix(':').unwrap_or(value).trim_start())
+}
+
+/// Read the element type and byte order from a descr such as ```<f8```
+fn parse_descr(descr: &str) -> io::Result<(Element, bool)> {
+    let mut chars = descr.chars();
+    let little_endian = match chars.next() {
+        Some('<') | Some('|') | Some('=') => true,
+        Some('>') => false,
+        _ => return Err(invalid(format!("unknown npy type {}", descr))),
+    };
+    let kind = chars.next();
+    let size = chars
+        .as_str()
+        .parse::<usize>()
+        .map_err(|_| invalid(format!("unknown npy type {}", descr)))?;
+    let element = match (kind, size) {
+        (Some('b'), 1) => Element::Bool,
+        (Some('i'), 1) | (Some('i'), 2) | (Some('i'), 4) | (Some('i'), 8) => {
+            Element::Integer(true, size)
+        }
+        (Some('u'), 1) | (Some('u'), 2) | (Some('u'), 4) | (Some('u'), 8) => {
+            Element::Integer(false, size)
+        }
+        (Some('f'), 4) | (Some('f'), 8) => Element::Float(size),
+        _ => return Err(invalid(format!("unsupported npy type {}", descr))),
+    };
+    Ok((element, little_endian))
+}
+
+/// Convert the bytes of one element to f64
+fn element_value(bytes: &[u8], element: Element, little_endian: bool) -> f64 {
+    let mut buffer = [0u8; 8];
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    if !little_endian {
+        buffer[..bytes.len()].reverse();
+    }
+    let bits = u64::from_le_bytes(buffer);
+    match element {
+        Element::Bool => (bits != 0) as u8 as f64,
+        Element::Integer(false, _) => bits as f64,
+        Element::Integer(true, size) => {
+            let shift = 64 - 8 * size as u32;
+            ((bits << shift) as i64 >> shift) as f64
+        }
+        Element::Float(4) => f64::from(f32::from_bits(bits as u32)),
+        Element::Float(_) => f64::from_bits(bits),
+    }
+}
+
+/// Read ```count``` bytes. The buffer grows as the bytes arrive rather than being allocated up
+/// front, so a header claiming more data than there is can't allocate more than the data
+fn read_bytes<R: Read>(reader: &mut R, count: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(count as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < count {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the npy file is shorter than its header says",
+        ));
+    }
+    Ok(bytes)
+}
+
+/// Read an array from ```.npy``` data
+pub fn read<R: Read>(mut reader: R) -> io::Result<Array> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic[..6] != b"\x93NUMPY" {
+        return Err(invalid("not an npy file"));
+    }
+    let header_length = if magic[6] == 1 {
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        u16::from_le_bytes(length) as usize
+    } else {
+        let mut length = [0u8; 4];
+        reader.read_exact(&mut length)?;
+        u32::from_le_bytes(length) as usize
+    };
+    let header = read_bytes(&mut reader, header_length)?;
+    let header = String::from_utf8_lossy(&header);
+
+    let descr = header_value(&header, "descr")?;
+    let descr = descr
+        .trim_start_matches('\'')
+        .split('\'')
+        .next()
+        .unwrap_or_default();
+    let (element, little_endian) = parse_descr(descr)?;
+    let fortran_order = header_value(&header, "fortran_order")?.starts_with("True");
+    let shape_text = header_value(&header, "shape")?;
+    let shape_end = shape_text
+        .find(')')
+        .ok_or_else(|| invalid("the npy shape is unreadable"))?;
+    let shape = shape_text[..shape_end]
+        .trim_start_matches('(')
+        .split(',')
+        .map(str::trim)
+        .filter(|dimension| !dimension.is_empty())
+        .map(|dimension| dimension.parse::<usize>().map_err(invalid))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let size = match element {
+        Element::Bool => 1,
+        Element::Integer(_, size) | Element::Float(size) => size,
+    };
+    let length = shape
+        .iter()
+        .try_fold(size, |length, &dimension| length.checked_mul(dimension))
+        .ok_or_else(|| invalid("the npy shape is too large"))?;
+    let bytes = read_bytes(&mut reader, length)?;
+    let mut data = bytes
+        .chunks(size)
+        .map(|chunk| element_value(chunk, element, little_endian))
+        .collect::<Vec<_>>();
+    if fortran_order && shape.len() == 2 {
+        let (rows, columns) = (shape[0], shape[1]);
+        data = (0..rows * columns)
+            .map(|i| data[(i % columns) * rows + i / columns])
+            .collect();
+    } else if fortran_order && shape.len() > 2 {
+        return Err(invalid("fortran ordered npy arrays must be 2d"));
+    }
+    Ok(Array {
+        element,
+        shape,
+        data,
+    })
+}
+
+/// Write a float array with the given shape, in C (row major) order.
+/// ```bytes``` is the size of each float, which is 4 or 8
+pub fn write_floats<W: Write>(
+    mut writer: W,
+    shape: &[usize],
+    data: &[f64],
+    bytes: usize,
+) -> io::Result<()> {
+    if bytes != 4 && bytes != 8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "npy floats must have 4 or 8 bytes",
+        ));
+    }
+    let count = shape
+        .iter()
+        .try_fold(1usize, |count, &dimension| count.checked_mul(dimension));
+    if count != Some(data.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the npy shape doesn't match the length of the data",
+        ));
+    }
+    let shape_text = match shape {
+        [length] => format!("({},)", length),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '<f{}', 'fortran_order': False, 'shape': {}, }}",
+        bytes, shape_text
+    );
+    // The magic, version and length take 10 bytes, and the data starts on a multiple of 64
+    let padding = (64 - (10 + header.len() + 1) % 64) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    write_raw_floats(writer, data, bytes)
+}
+
+/// Write floats as raw little endian binary, with 4 or 8 bytes for each
+pub fn write_raw_floats<W: Write>(mut writer: W, data: &[f64], bytes: usize) -> io::Result<()> {
+    for &value in data {
+        if bytes == 4 {
+            writer.write_all(&(value as f32).to_le_bytes())?;
+        } else {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bytes of an npy file with the given header dictionary and data
+    fn npy_bytes(header: &str, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn reads_integers_of_either_byte_order() {
+        let little = npy_bytes(
+            "{'descr': '<i2', 'fortran_order': False, 'shape': (3,), }\n",
+            &[1, 0, 0xff, 0xff, 0, 1],
+        );
+        let array = read(little.as_slice()).unwrap();
+        assert_eq!(array.element, Element::Integer(true, 2));
+        assert_eq!(array.shape, vec![3]);
+        assert_eq!(array.data, vec![1.0, -1.0, 256.0]);
+
+        let big = npy_bytes(
+            "{'descr': '>u2', 'fortran_order': False, 'shape': (2,), }\n",
+            &[1, 0, 0xff, 0xff],
+        );
+        assert_eq!(read(big.as_slice()).unwrap().data, vec![256.0, 65535.0]);
+    }
+
+    #[test]
+    fn reads_fortran_order_as_rows() {
+        let bytes = npy_bytes(
+            "{'descr': '|u1', 'fortran_order': True, 'shape': (2, 3), }\n",
+            &[1, 4, 2, 5, 3, 6],
+        );
+        let array = read(bytes.as_slice()).unwrap();
+        assert_eq!(array.shape, vec![2, 3]);
+        assert_eq!(array.data, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn rejects_unsupported_types() {
+        for descr in &["<c16", "<f2", "<U8", "|O", "f8"] {
+            let header = format!(
+                "{{'descr': '{}', 'fortran_order': False, 'shape': (1,), }}\n",
+                descr
+            );
+            let bytes = npy_bytes(&header, &[0; 16]);
+            let error = read(bytes.as_slice()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", descr);
+        }
+    }
+
+    #[test]
+    fn round_trips_floats() {
+        let data = [0.0, -1.5, 2.25, 1e-3, 6.0, 7.0];
+        for &bytes in &[4, 8] {
+            let mut file = Vec::new();
+            write_floats(&mut file, &[2, 3], &data, bytes).unwrap();
+            assert_eq!((file.len() - data.len() * bytes) % 64, 0);
+            let array = read(file.as_slice()).unwrap();
+            assert_eq!(array.element, Element::Float(bytes));
+            assert_eq!(array.shape, vec![2, 3]);
+            for (read, written) in array.data.iter().zip(data.iter()) {
+                assert!((read - written).abs() < 1e-6);
+            }
+        }
+        let mut file = Vec::new();
+        write_floats(&mut file, &[4], &data[..4], 8).unwrap();
+        assert_eq!(read(file.as_slice()).unwrap().shape, vec![4]);
+    }
+
+    #[test]
+    fn rejects_writes_which_dont_match_the_shape() {
+        let mut file = Vec::new();
+        let error = write_floats(&mut file, &[2, 2], &[0.0; 3], 8).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let error = write_floats(&mut file, &[3], &[0.0; 3], 2).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(file.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_data_and_other_files() {
+        let short = npy_bytes(
+            "{'descr': '<f8', 'fortran_order': False, 'shape': (2, 2), }\n",
+            &[0; 24],
+        );
+        assert!(read(short.as_slice()).is_err());
+        assert!(read(&b"\x89PNG\r\n\x1a\n\0\0"[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_shapes_larger_than_the_data() {
+        let overflowing = npy_bytes(
+            "{'descr': '<f8', 'fortran_order': False, 'shape': (4294967296, 4294967296), }\n",
+            &[0; 8],
+        );
+        let error = read(overflowing.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // this would be a 16 GB buffer if it were allocated before the data was read
+        let huge = npy_bytes(
+            "{'descr': '<f8', 'fortran_order': False, 'shape': (2000000000,), }\n",
+            &[0; 8],
+        );
+        let error = read(huge.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
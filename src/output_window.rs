@@ -0,0 +1,174 @@
+//! This module contains the borderless window which shows the pattern on the SLM.
+//! The pattern is drawn pixel for pixel, without any scaling or filtering
+
+use cairo::PatternTrait;
+use gdk::{ContextExt, WindowExt};
+use gtk::{ContainerExt, GtkWindowExt, Inhibit, WidgetExt};
+use relm::{Relm, Update, Widget};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use self::OutputWindowMsg::*;
+
+/// Get a description of each monitor attached to the default display, in monitor order
+pub fn monitor_names() -> Vec<String> {
+    use gdk::MonitorExt;
+    let display = match gdk::Display::get_default() {
+        Some(display) => display,
+        None => return vec![],
+    };
+    (0..display.get_n_monitors())
+        .filter_map(|i| display.get_monitor(i).map(|monitor| (i, monitor)))
+        .map(|(i, monitor)| {
+            let geometry = monitor.get_geometry();
+            format!(
+                "{}: {} ({}x{})",
+                i,
+                monitor
+                    .get_model()
+                    .map(|model| model.to_string())
+                    .unwrap_or_else(|| String::from("unknown")),
+                geometry.width,
+                geometry.height
+            )
+        })
+        .collect()
+}
+
+/// The model for the output window
+pub struct OutputWindowModel {
+    /// The pattern being shown. This is shared with the draw callback of the window
+    image: Rc<RefCell<Option<gdk_pixbuf::Pixbuf>>>,
+    /// Whether the image has been changed since it was last drawn
+    changed: Rc<Cell<bool>>,
+    monitor: i32,
+    visible: bool,
+}
+
+/// The messages which the output window accepts
+#[derive(Msg)]
+pub enum OutputWindowMsg {
+    SetImage(gdk_pixbuf::Pixbuf),
+    SetMonitor(i32),
+    SetVisible(bool),
+    // Sent once a new image has been drawn in the window
+    Presented,
+}
+
+/// A fullscreen, undecorated window which displays the pattern on a chosen monitor
+pub struct OutputWindow {
+    model: OutputWindowModel,
+    window: gtk::Window,
+    drawing_area: gtk::DrawingArea,
+}
+
+impl OutputWindow {
+    /// Move the window onto the chosen monitor, and make it fill the monitor
+    fn place_on_monitor(&self) {
+        if let Some(display) = gdk::Display::get_default() {
+            if self.model.monitor < display.get_n_monitors() {
+                self.window
+                    .fullscreen_on_monitor(&display.get_default_screen(), self.model.monitor);
+            }
+        }
+    }
+
+    /// Hide the mouse cursor while it is over the window, so it doesn't end up in the pattern
+    fn hide_cursor(&self) {
+        if let Some(gdk_window) = self.window.get_window() {
+            let cursor =
+                gdk::Cursor::new_for_display(&gdk_window.get_display(), gdk::CursorType::BlankCursor);
+            gdk_window.set_cursor(&cursor);
+        }
+    }
+
+    fn set_visible(&mut self, visible: bool) {
+        self.model.visible = visible;
+        if visible {
+            self.place_on_monitor();
+            self.window.show_all();
+            self.hide_cursor();
+        } else {
+            self.window.hide();
+        }
+    }
+}
+
+impl Update for OutputWindow {
+    type Model = OutputWindowModel;
+    type ModelParam = ();
+    type Msg = OutputWindowMsg;
+
+    fn model(_: &Relm<Self>, _: Self::ModelParam) -> Self::Model {
+        OutputWindowModel {
+            image: Rc::new(RefCell::new(None)),
+            changed: Rc::new(Cell::new(false)),
+            monitor: 0,
+            visible: false,
+        }
+    }
+
+    fn update(&mut self, event: Self::Msg) {
+        match event {
+            SetImage(pixbuf) => {
+                *self.model.image.borrow_mut() = Some(pixbuf);
+                self.model.changed.set(true);
+                self.drawing_area.queue_draw();
+            }
+            SetMonitor(monitor) => {
+                self.model.monitor = monitor;
+                if self.model.visible {
+                    self.place_on_monitor();
+                }
+            }
+            SetVisible(visible) => self.set_visible(visible),
+            Presented => (),
+        }
+    }
+}
+
+impl Widget for OutputWindow {
+    type Root = gtk::Window;
+
+    fn root(&self) -> Self::Root {
+        self.window.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+        let window = gtk::Window::new(gtk::WindowType::Toplevel);
+        window.set_title("Phase output");
+        window.set_decorated(false);
+        let drawing_area = gtk::DrawingArea::new();
+        let image = model.image.clone();
+        let changed = model.changed.clone();
+        let stream = relm.stream().clone();
+        drawing_area.connect_draw(move |widget, context| {
+            context.set_source_rgb(0.0, 0.0, 0.0);
+            context.paint();
+            if let Some(ref pixbuf) = *image.borrow() {
+                // draw in device pixels, so one pixel of the pattern is one pixel of the SLM
+                let scale = widget.get_scale_factor() as f64;
+                context.scale(1.0 / scale, 1.0 / scale);
+                context.set_source_pixbuf(pixbuf, 0.0, 0.0);
+                context.get_source().set_filter(cairo::Filter::Nearest);
+                context.paint();
+                if changed.replace(false) {
+                    stream.emit(Presented);
+                }
+            }
+            Inhibit(false)
+        });
+        // the window is only hidden when closed, so it can be shown again
+        window.connect_delete_event(|window, _| {
+            window.hide();
+            Inhibit(true)
+        });
+        window.add(&drawing_area);
+
+        OutputWindow {
+            model,
+            window,
+            drawing_area,
+        }
+    }
+}
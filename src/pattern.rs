@@ -0,0 +1,621 @@
+//! This module contains the kinds of pattern which can be put into a container.
+//! Each kind implements ```Pattern```, which gives its field and the parameters which control it.
+//! To add a new kind, implement ```Pattern``` for its data, and add it to ```PatternKind```
+use num_complex::Complex64;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
+use crate::modes;
+use crate::slm_data::{
+    AxiconData, ImageData, HermiteGaussData, LaguerreGaussData, PatternData, Trap, TrapsData,
+};
+
+/// A parameter of a pattern, which is shown as a spin button in the GUI
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+    /// The name used to set the parameter, following the field it sets (```k.0```, ```phase```...)
+    pub name: &'static str,
+    /// The label shown next to the spin button
+    pub label: &'static str,
+    pub value: f64,
+    /// The amount the spin button changes by in one step
+    pub step: f64,
+    /// The number of decimal places shown. Integer parameters have no decimal places
+    pub digits: u32,
+}
+
+impl Parameter {
+    fn new(name: &'static str, label: &'static str, value: f64) -> Parameter {
+        Parameter {
+            name,
+            label,
+            value,
+            step: 1.0,
+            digits: 3,
+        }
+    }
+
+    fn integer(name: &'static str, label: &'static str, value: f64) -> Parameter {
+        Parameter {
+            digits: 0,
+            ..Parameter::new(name, label, value)
+        }
+    }
+
+    fn with_step(self, step: f64, digits: u32) -> Parameter {
+        Parameter {
+            step,
+            digits,
+            ..self
+        }
+    }
+}
+
+/// A pattern which can be displayed in a container
+pub trait Pattern {
+    /// The complex field of the pattern at the point (x, y).
+    /// (x, y) are measured in the container's coordinates (after the container offset and scale)
+    fn field(&self, x: f64, y: f64) -> Complex64;
+
+    /// The complex field of the pattern at the point (x, y), when the SLM is lit there with the
+    /// amplitude ```illumination``` (between 0 and 1). Patterns which encode an amplitude
+    /// correct it for the illumination, and the others ignore it
+    fn lit_field(&self, x: f64, y: f64, _illumination: f64) -> Complex64 {
+        self.field(x, y)
+    }
+
+    /// The peak of the amplitude which amplitude encoded patterns are normalised by.
+    /// It only depends on the pattern's parameters, so renders find it once with this and pass
+    /// it to ```peak_field``` for each pixel
+    fn peak(&self) -> f64 {
+        1.0
+    }
+
+    /// The same as ```lit_field```, with the pattern's ```peak``` already found
+    fn peak_field(&self, x: f64, y: f64, illumination: f64, _peak: f64) -> Complex64 {
+        self.lit_field(x, y, illumination)
+    }
+
+    /// The amplitude ```a``` the pattern is shown with
+    fn amplitude(&self) -> f64;
+
+    /// The parameters which control the pattern, in the order they are shown
+    fn parameters(&self) -> Vec<Parameter>;
+
+    /// Set the parameter with the given name. Returns false if the pattern has no such parameter
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool;
+}
+
+/// A vortex with a linear grating and a lens
+impl Pattern for PatternData {
+    fn field(&self, x: f64, y: f64) -> Complex64 {
+        let (dx, dy) = (x - self.c.0, y - self.c.1);
+        let phase = self.l as f64 * dy.atan2(dx)
+            + self.k.0 * x
+            + self.k.1 * y
+            + self.lens * (dx * dx + dy * dy)
+            + self.phase;
+        Complex64::from_polar(&self.a, &phase)
+    }
+
+    fn amplitude(&self) -> f64 {
+        self.a
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter::integer("l", "l", self.l as f64),
+            Parameter::new("a", "a", self.a),
+            Parameter::new("k.0", "kx", self.k.0),
+            Parameter::new("k.1", "ky", self.k.1),
+            Parameter::new("c.0", "cx", self.c.0),
+            Parameter::new("c.1", "cy", self.c.1),
+            Parameter::new("phase", "φ", self.phase),
+            Parameter::new("lens", "lens", self.lens).with_step(0.0001, 6),
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "l" => self.l = value.round() as i32,
+            "a" => self.a = value,
+            "k.0" => self.k.0 = value,
+            "k.1" => self.k.1 = value,
+            "c.0" => self.c.0 = value,
+            "c.1" => self.c.1 = value,
+            "phase" => self.phase = value,
+            "lens" => self.lens = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// A conical phase, with a vortex and a linear grating
+impl Pattern for AxiconData {
+    fn field(&self, x: f64, y: f64) -> Complex64 {
+        let (dx, dy) = (x - self.c.0, y - self.c.1);
+        let phase = self.l as f64 * dy.atan2(dx)
+            + self.kr * (dx * dx + dy * dy).sqrt()
+            + self.k.0 * x
+            + self.k.1 * y
+            + self.phase;
+        Complex64::from_polar(&self.a, &phase)
+    }
+
+    fn amplitude(&self) -> f64 {
+        self.a
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter::integer("l", "l", self.l as f64),
+            Parameter::new("a", "a", self.a),
+            Parameter::new("kr", "kr", self.kr).with_step(0.01, 4),
+            Parameter::new("k.0", "kx", self.k.0),
+            Parameter::new("k.1", "ky", self.k.1),
+            Parameter::new("c.0", "cx", self.c.0),
+            Parameter::new("c.1", "cy", self.c.1),
+            Parameter::new("phase", "φ", self.phase),
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "l" => self.l = value.round() as i32,
+            "a" => self.a = value,
+            "kr" => self.kr = value,
+            "k.0" => self.k.0 = value,
+            "k.1" => self.k.1 = value,
+            "c.0" => self.c.0 = value,
+            "c.1" => self.c.1 = value,
+            "phase" => self.phase = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// A Laguerre–Gaussian mode, with its amplitude encoded into the phase
+impl Pattern for LaguerreGaussData {
+    fn field(&self, x: f64, y: f64) -> Complex64 {
+        self.lit_field(x, y, 1.0)
+    }
+
+    fn lit_field(&self, x: f64, y: f64, illumination: f64) -> Complex64 {
+        self.peak_field(x, y, illumination, self.peak())
+    }
+
+    fn peak(&self) -> f64 {
+        modes::laguerre_gauss_peak(self.p, self.l)
+    }
+
+    fn peak_field(&self, x: f64, y: f64, illumination: f64, peak: f64) -> Complex64 {
+        if self.waist <= 0.0 {
+            return Complex64::new(0.0, 0.0);
+        }
+        let (dx, dy) = (x - self.c.0, y - self.c.1);
+        let rho = 2f64.sqrt() * (dx * dx + dy * dy).sqrt() / self.waist;
+        let mode = modes::laguerre_gauss(self.p, self.l, rho);
+        let mode_phase = self.l as f64 * dy.atan2(dx) + if mode < 0.0 { PI } else { 0.0 };
+        let phase = modes::encode_amplitude(
+            modes::compensate_illumination(mode.abs() / peak, illumination),
+            mode_phase + self.k.0 * x + self.k.1 * y + self.phase,
+        );
+        Complex64::from_polar(&self.a, &phase)
+    }
+
+    fn amplitude(&self) -> f64 {
+        self.a
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter::integer("p", "p", f64::from(self.p)),
+            Parameter::integer("l", "l", self.l as f64),
+            Parameter::new("a", "a", self.a),
+            Parameter::new("waist", "w", self.waist),
+            Parameter::new("k.0", "kx", self.k.0),
+            Parameter::new("k.1", "ky", self.k.1),
+            Parameter::new("c.0", "cx", self.c.0),
+            Parameter::new("c.1", "cy", self.c.1),
+            Parameter::new("phase", "φ", self.phase),
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "p" => self.p = value.round().max(0.0) as u32,
+            "l" => self.l = value.round() as i32,
+            "a" => self.a = value,
+            "waist" => self.waist = value,
+            "k.0" => self.k.0 = value,
+            "k.1" => self.k.1 = value,
+            "c.0" => self.c.0 = value,
+            "c.1" => self.c.1 = value,
+            "phase" => self.phase = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// A Hermite–Gaussian mode, with its amplitude encoded into the phase
+impl Pattern for HermiteGaussData {
+    fn field(&self, x: f64, y: f64) -> Complex64 {
+        self.lit_field(x, y, 1.0)
+    }
+
+    fn lit_field(&self, x: f64, y: f64, illumination: f64) -> Complex64 {
+        self.peak_field(x, y, illumination, self.peak())
+    }
+
+    fn peak(&self) -> f64 {
+        modes::hermite_gauss_peak(self.m) * modes::hermite_gauss_peak(self.n)
+    }
+
+    fn peak_field(&self, x: f64, y: f64, illumination: f64, peak: f64) -> Complex64 {
+        if self.waist <= 0.0 {
+            return Complex64::new(0.0, 0.0);
+        }
+        let scale = 2f64.sqrt() / self.waist;
+        let mode = modes::hermite_gauss(self.m, (x - self.c.0) * scale)
+            * modes::hermite_gauss(self.n, (y - self.c.1) * scale);
+        let mode_phase = if mode < 0.0 { PI } else { 0.0 };
+        let phase = modes::encode_amplitude(
+            modes::compensate_illumination(mode.abs() / peak, illumination),
+            mode_phase + self.k.0 * x + self.k.1 * y + self.phase,
+        );
+        Complex64::from_polar(&self.a, &phase)
+    }
+
+    fn amplitude(&self) -> f64 {
+        self.a
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter::integer("m", "m", f64::from(self.m)),
+            Parameter::integer("n", "n", f64::from(self.n)),
+            Parameter::new("a", "a", self.a),
+            Parameter::new("waist", "w", self.waist),
+            Parameter::new("k.0", "kx", self.k.0),
+            Parameter::new("k.1", "ky", self.k.1),
+            Parameter::new("c.0", "cx", self.c.0),
+            Parameter::new("c.1", "cy", self.c.1),
+            Parameter::new("phase", "φ", self.phase),
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "m" => self.m = value.round().max(0.0) as u32,
+            "n" => self.n = value.round().max(0.0) as u32,
+            "a" => self.a = value,
+            "waist" => self.waist = value,
+            "k.0" => self.k.0 = value,
+            "k.1" => self.k.1 = value,
+            "c.0" => self.c.0 = value,
+            "c.1" => self.c.1 = value,
+            "phase" => self.phase = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// A phase map, with its top left pixel at the origin
+impl Pattern for ImageData {
+    fn field(&self, x: f64, y: f64) -> Complex64 {
+        let phase = if x < 0.0 || y < 0.0 {
+            None
+        } else {
+            self.map()
+                .and_then(|map| map.get(x.floor() as usize, y.floor() as usize))
+        };
+        match phase {
+            Some(phase) => Complex64::from_polar(&self.a, &phase),
+            None => Complex64::new(0.0, 0.0),
+        }
+    }
+
+    fn amplitude(&self) -> f64 {
+        self.a
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter::new("a", "a", self.a),
+            Parameter::new("two_pi_level", "2π level", self.two_pi_level.unwrap_or(0.0)),
+        ]
+    }
+
+    /// A 2π level of 0 or less uses the whole range of the image
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "a" => self.a = value,
+            "two_pi_level" => {
+                self.two_pi_level = if value > 0.0 { Some(value) } else { None };
+                self.map = OnceLock::new();
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// The phase of the trap's grating and lens at (x, y), where (dx, dy) is measured from the
+/// centre of the lenses
+pub fn trap_phase(trap: &Trap, x: f64, y: f64, dx: f64, dy: f64) -> f64 {
+    trap.k.0 * x + trap.k.1 * y + trap.z * (dx * dx + dy * dy)
+}
+
+/// The superposition of the gratings and lenses of an array of traps
+impl Pattern for TrapsData {
+    fn field(&self, x: f64, y: f64) -> Complex64 {
+        let (dx, dy) = (x - self.c.0, y - self.c.1);
+        let field: Complex64 = self
+            .traps
+            .iter()
+            .map(|trap| {
+                Complex64::from_polar(
+                    &(trap.weight.max(0.0).sqrt() * trap.gain),
+                    &(trap_phase(trap, x, y, dx, dy) + trap.phase),
+                )
+            })
+            .sum();
+        if field.norm() > 0.0 {
+            field / field.norm() * self.a
+        } else {
+            field
+        }
+    }
+
+    fn amplitude(&self) -> f64 {
+        self.a
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter::new("a", "a", self.a),
+            Parameter::new("c.0", "cx", self.c.0),
+            Parameter::new("c.1", "cy", self.c.1),
+            Parameter::integer("iterations", "iterations", self.iterations as f64),
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "a" => self.a = value,
+            "c.0" => self.c.0 = value,
+            "c.1" => self.c.1 = value,
+            "iterations" => self.iterations = value.round().max(0.0) as usize,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Every kind of pattern. In saved files the kind is given by the ```kind``` field
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PatternKind {
+    Vortex(PatternData),
+    Axicon(AxiconData),
+    LaguerreGauss(LaguerreGaussData),
+    HermiteGauss(HermiteGaussData),
+    Image(ImageData),
+    Traps(TrapsData),
+}
+
+impl PatternKind {
+    /// The names of the kinds of pattern, in the order they are offered in the GUI
+    pub const NAMES: &'static [&'static str] = &[
+        "vortex",
+        "axicon",
+        "laguerre_gauss",
+        "hermite_gauss",
+        "image",
+        "traps",
+    ];
+
+    /// A new pattern of the named kind, with unit amplitude
+    pub fn new(name: &str) -> Option<PatternKind> {
+        match name {
+            "vortex" => Some(PatternKind::Vortex(PatternData {
+                a: 1.0,
+                ..Default::default()
+            })),
+            "axicon" => Some(PatternKind::Axicon(AxiconData {
+                a: 1.0,
+                kr: 0.1,
+                ..Default::default()
+            })),
+            "laguerre_gauss" => Some(PatternKind::LaguerreGauss(LaguerreGaussData {
+                l: 1,
+                a: 1.0,
+                waist: 100.0,
+                k: (0.5, 0.0),
+                ..Default::default()
+            })),
+            "hermite_gauss" => Some(PatternKind::HermiteGauss(HermiteGaussData {
+                m: 1,
+                a: 1.0,
+                waist: 100.0,
+                k: (0.5, 0.0),
+                ..Default::default()
+            })),
+            "image" => Some(PatternKind::Image(ImageData {
+                a: 1.0,
+                ..Default::default()
+            })),
+            "traps" => Some(PatternKind::Traps(TrapsData {
+                a: 1.0,
+                ..Default::default()
+            })),
+            _ => None,
+        }
+    }
+
+    /// The name of the kind of the pattern
+    pub fn name(&self) -> &'static str {
+        match self {
+            PatternKind::Vortex(_) => "vortex",
+            PatternKind::Axicon(_) => "axicon",
+            PatternKind::LaguerreGauss(_) => "laguerre_gauss",
+            PatternKind::HermiteGauss(_) => "hermite_gauss",
+            PatternKind::Image(_) => "image",
+            PatternKind::Traps(_) => "traps",
+        }
+    }
+
+    fn pattern(&self) -> &dyn Pattern {
+        match self {
+            PatternKind::Vortex(p) => p,
+            PatternKind::Axicon(p) => p,
+            PatternKind::LaguerreGauss(p) => p,
+            PatternKind::HermiteGauss(p) => p,
+            PatternKind::Image(p) => p,
+            PatternKind::Traps(p) => p,
+        }
+    }
+
+    fn pattern_mut(&mut self) -> &mut dyn Pattern {
+        match self {
+            PatternKind::Vortex(p) => p,
+            PatternKind::Axicon(p) => p,
+            PatternKind::LaguerreGauss(p) => p,
+            PatternKind::HermiteGauss(p) => p,
+            PatternKind::Image(p) => p,
+            PatternKind::Traps(p) => p,
+        }
+    }
+}
+
+impl Pattern for PatternKind {
+    fn field(&self, x: f64, y: f64) -> Complex64 {
+        self.pattern().field(x, y)
+    }
+
+    fn lit_field(&self, x: f64, y: f64, illumination: f64) -> Complex64 {
+        self.pattern().lit_field(x, y, illumination)
+    }
+
+    fn peak(&self) -> f64 {
+        self.pattern().peak()
+    }
+
+    fn peak_field(&self, x: f64, y: f64, illumination: f64, peak: f64) -> Complex64 {
+        self.pattern().peak_field(x, y, illumination, peak)
+    }
+
+    fn amplitude(&self) -> f64 {
+        self.pattern().amplitude()
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        self.pattern().parameters()
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        self.pattern_mut().set_parameter(name, value)
+    }
+}
+
+impl From<PatternData> for PatternKind {
+    fn from(pattern: PatternData) -> Self {
+        PatternKind::Vortex(pattern)
+    }
+}
+
+/// Deserialize the patterns of a container.
+/// Files from before there were kinds of pattern only hold vortex data, with no ```kind```
+/// field, so patterns without a kind are read as vortices. A pattern with a kind which isn't
+/// known is an error, rather than being read as a vortex
+pub fn deserialize_patterns<'de, D>(deserializer: D) -> Result<HashMap<usize, PatternKind>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let patterns = HashMap::<usize, serde_json::Value>::deserialize(deserializer)?;
+    patterns
+        .into_iter()
+        .map(|(id, pattern)| {
+            let pattern = if pattern.get("kind").is_some() {
+                serde_json::from_value::<PatternKind>(pattern)
+            } else {
+                serde_json::from_value::<PatternData>(pattern).map(PatternKind::from)
+            };
+            pattern
+                .map(|pattern| (id, pattern))
+                .map_err(|e| D::Error::custom(format!("pattern {}: {}", id, e)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slm_data::PatternContainerData;
+
+    fn container(patterns: &str) -> serde_json::Result<PatternContainerData> {
+        serde_json::from_str(&format!(
+            r#"{{"top_left": [0, 0], "bottom_right": [10, 10], "pos": [0, 0],
+                "scale": [1, 1], "patterns": {}}}"#,
+            patterns
+        ))
+    }
+
+    #[test]
+    fn patterns_without_a_kind_are_vortices() {
+        let container =
+            container(r#"{"0": {"l": 2, "a": 1, "k": [0, 0], "c": [0, 0], "phase": 0}}"#)
+                .unwrap();
+        match &container.patterns[&0] {
+            PatternKind::Vortex(vortex) => assert_eq!(vortex.l, 2),
+            pattern => panic!("read a {} pattern", pattern.name()),
+        }
+    }
+
+    #[test]
+    fn unknown_kinds_are_errors() {
+        let error = container(
+            r#"{"3": {"kind": "votrex", "l": 2, "a": 1, "k": [0, 0], "c": [0, 0], "phase": 0}}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("pattern 3"), "{}", error);
+    }
+
+    #[test]
+    fn patterns_can_be_shared_with_render_threads() {
+        fn assert_sync<T: Send + Sync>() {}
+        assert_sync::<PatternKind>();
+    }
+
+    #[test]
+    fn peak_field_matches_lit_field() {
+        for name in &["laguerre_gauss", "hermite_gauss"] {
+            let pattern = PatternKind::new(name).unwrap();
+            let peak = pattern.peak();
+            assert!(peak > 0.0);
+            for &(x, y) in &[(0.0, 0.0), (30.0, -20.0), (150.0, 90.0)] {
+                assert_eq!(
+                    pattern.peak_field(x, y, 0.8, peak),
+                    pattern.lit_field(x, y, 0.8)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn kinds_round_trip() {
+        for name in PatternKind::NAMES {
+            let pattern = PatternKind::new(name).unwrap();
+            let json = serde_json::to_string(&pattern).unwrap();
+            let container = container(&format!(r#"{{"0": {}}}"#, json)).unwrap();
+            assert_eq!(container.patterns[&0].name(), *name);
+        }
+    }
+}
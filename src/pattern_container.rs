@@ -1,15 +1,18 @@
 //! This module contains the definition of the container for a group of patterns
 use gtk::{
-    BoxExt, ButtonExt, EntryExt, GridExt, Orientation, ScrolledWindowExt, SpinButtonExt, ContainerExt, ViewportExt,
-    SpinButtonSignals, WidgetExt,
+    BoxExt, ButtonExt, DialogExt, ComboBoxExt, ComboBoxExtManual, ComboBoxTextExt, EntryExt, GridExt, Orientation, SpinButtonExt, ContainerExt,
+    FileChooserButtonExt, FileChooserExt, SpinButtonSignals, ToggleButtonExt, WidgetExt,
 };
+use gtk::Cast;
 use relm::{Component, ContainerWidget, Relm, Update, Widget};
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 use self::PatternContainerMsg::*;
-use crate::gui::{SLMController, SLMControllerMsg};
-use crate::pattern_controller::PatternController;
-use crate::slm_data::*;
+use crate::gui::{show_error, SLMController, SLMControllerMsg};
+use crate::pattern_controller::{PatternController, PatternControllerMsg};
+use phase::pattern::PatternKind;
+use phase::slm_data::*;
 
 /// The model for the pattern container
 #[derive(Clone)]
@@ -18,19 +21,21 @@ pub struct PatternContainerModel {
     parent_relm: Relm<SLMController>,
     current_controller_id: usize,
     id: usize,
+    /// The beam lighting the container, which is kept while the container isn't lit
+    illumination: Illumination,
+    /// Whether the container is lit by ```illumination```
+    illuminated: bool,
 }
 
 /// The messages that the container accepts
 #[derive(Msg)]
 pub enum PatternContainerMsg {
-    AddPattern(PatternData),
-    UpdatePatternL(usize, i32),
-    UpdatePatternA(usize, f64),
-    UpdatePatternKx(usize, f64),
-    UpdatePatternKy(usize, f64),
-    UpdatePatternCx(usize, f64),
-    UpdatePatternCy(usize, f64),
-    UpdatePatternPhase(usize, f64),
+    AddPattern(PatternKind),
+    InsertPattern(usize, PatternKind),
+    SetPatternParameter(usize, String, f64),
+    SetGeometry(PatternContainerData),
+    AddNewPattern,
+    UpdatePatternParameter(usize, String, f64),
     UpdateContainerCx(f64),
     UpdateContainerCy(f64),
     UpdateContainerScaleX(f64),
@@ -39,6 +44,13 @@ pub enum PatternContainerMsg {
     UpdateContainerTLY(f64),
     UpdateContainerBRX(f64),
     UpdateContainerBRY(f64),
+    UpdateContainerCombination(CombinationMode),
+    SetIlluminated(bool),
+    UpdateIlluminationWaist(f64),
+    UpdateIlluminationCx(f64),
+    UpdateIlluminationCy(f64),
+    UpdateIlluminationWavelength(f64),
+    SetIlluminationImage(Option<PathBuf>),
     DeletePattern(usize),
 }
 
@@ -47,6 +59,12 @@ pub struct PatternContainer {
     model: PatternContainerModel,
     root_box: gtk::Box,
     pattern_box: gtk::Box,
+    /// chooses the kind of pattern the add pattern button adds
+    kind_combo: gtk::ComboBoxText,
+    /// chooses the measured intensity of the container's illumination
+    illumination_image: gtk::FileChooserButton,
+    /// the spin buttons for the top left, bottom right, pos and scale, with x before y
+    geometry_spins: Vec<gtk::SpinButton>,
     relm: Relm<Self>,
     parent_relm: Relm<SLMController>,
     patterns: HashMap<usize, Component<PatternController>>,
@@ -54,22 +72,89 @@ pub struct PatternContainer {
 
 impl PatternContainer {
     /// Add a new pattern to this pattern container
-    pub fn add_new_pattern(&mut self, pattern: PatternData) {
+    pub fn add_new_pattern(&mut self, pattern: PatternKind) {
+        self.insert_pattern(self.model.current_controller_id, pattern);
+    }
+
+    /// Add a pattern with the given id to this pattern container
+    pub fn insert_pattern(&mut self, id: usize, pattern: PatternKind) {
+        if let Some(old) = self.patterns.remove(&id) {
+            self.pattern_box.remove_widget(old);
+        }
         let widget = self.pattern_box.add_widget::<PatternController>((
             pattern.clone(),
-            self.model.current_controller_id,
+            id,
             self.relm.clone(),
         ));
-        self.patterns
-            .insert(self.model.current_controller_id, widget);
+        self.patterns.insert(id, widget);
+        self.parent_relm
+            .stream()
+            .emit(SLMControllerMsg::AddController(self.model.id, id, pattern));
+        self.model.current_controller_id = self.model.current_controller_id.max(id + 1);
+    }
+
+    /// Show the crop rectangle, pos and scale of the data in the spin buttons
+    fn set_geometry(&self, data: &PatternContainerData) {
+        let values = [
+            data.top_left.0,
+            data.top_left.1,
+            data.bottom_right.0,
+            data.bottom_right.1,
+            data.pos.0,
+            data.pos.1,
+            data.scale.0,
+            data.scale.1,
+        ];
+        for (spin, &value) in self.geometry_spins.iter().zip(values.iter()) {
+            spin.set_value(value);
+        }
+    }
+
+    /// Ask for the phase map file of a new image pattern
+    fn choose_image(&self) -> Option<ImageData> {
+        use gtk::ResponseType;
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Load phase image"),
+            None::<&gtk::Window>,
+            gtk::FileChooserAction::Open,
+            &[
+                ("_Cancel", ResponseType::Cancel),
+                ("_Open", ResponseType::Accept),
+            ],
+        );
+        let filename = if ResponseType::from(dialog.run()) == ResponseType::Accept {
+            dialog.get_filename()
+        } else {
+            None
+        };
+        dialog.emit_close();
+        let filename = filename?;
+        ImageData::open(filename.clone(), None)
+            .map_err(|e| {
+                show_error(
+                    self.root_box
+                        .get_toplevel()
+                        .and_then(|window| window.downcast::<gtk::Window>().ok())
+                        .as_ref(),
+                    &format!("Couldn't load {}: {}", filename.display(), e),
+                )
+            })
+            .ok()
+    }
+
+    /// Send the container's illumination to the controller
+    fn emit_illumination(&self) {
+        let illumination = if self.model.illuminated {
+            Some(self.model.illumination.clone())
+        } else {
+            None
+        };
         self.parent_relm
             .stream()
-            .emit(SLMControllerMsg::AddController(
+            .emit(SLMControllerMsg::UpdateContainerIllumination(
                 self.model.id,
-                self.model.current_controller_id,
-                pattern,
+                illumination,
             ));
-        self.model.current_controller_id += 1;
     }
 
     /// Delete the pattern at `id` from this container
@@ -89,7 +174,15 @@ impl Update for PatternContainer {
     type Msg = PatternContainerMsg;
 
     fn model(_: &Relm<Self>, param: Self::ModelParam) -> Self::Model {
+        let illuminated = param.0.illumination.is_some();
+        let illumination = param
+            .0
+            .illumination
+            .clone()
+            .unwrap_or_else(|| Illumination::gaussian(100.0));
         PatternContainerModel {
+            illumination,
+            illuminated,
             patterns_data: param.0,
             parent_relm: param.1,
             id: param.2,
@@ -101,35 +194,38 @@ impl Update for PatternContainer {
         use crate::gui::SLMControllerMsg;
         match event {
             AddPattern(p) => self.add_new_pattern(p),
+            InsertPattern(id, p) => self.insert_pattern(id, p),
+            SetPatternParameter(id, name, x) => {
+                if let Some(pattern) = self.patterns.get(&id) {
+                    pattern
+                        .stream()
+                        .emit(PatternControllerMsg::SetParameter(name, x));
+                }
+            }
+            SetGeometry(data) => self.set_geometry(&data),
+            AddNewPattern => {
+                let kind = self
+                    .kind_combo
+                    .get_active_text()
+                    .and_then(|name| PatternKind::new(&name));
+                let kind = match kind {
+                    Some(PatternKind::Image(_)) => self.choose_image().map(PatternKind::Image),
+                    kind => kind,
+                };
+                if let Some(pattern) = kind {
+                    self.add_new_pattern(pattern);
+                }
+            }
             DeletePattern(id) => self.delete_pattern(id),
-            UpdatePatternL(id, x) => self
+            UpdatePatternParameter(id, name, x) => self
                 .parent_relm
                 .stream()
-                .emit(SLMControllerMsg::UpdatePatternL(self.model.id, id, x)),
-            UpdatePatternA(id, x) => self
-                .parent_relm
-                .stream()
-                .emit(SLMControllerMsg::UpdatePatternA(self.model.id, id, x)),
-            UpdatePatternKx(id, x) => self
-                .parent_relm
-                .stream()
-                .emit(SLMControllerMsg::UpdatePatternKx(self.model.id, id, x)),
-            UpdatePatternKy(id, x) => self
-                .parent_relm
-                .stream()
-                .emit(SLMControllerMsg::UpdatePatternKy(self.model.id, id, x)),
-            UpdatePatternCx(id, x) => self
-                .parent_relm
-                .stream()
-                .emit(SLMControllerMsg::UpdatePatternCx(self.model.id, id, x)),
-            UpdatePatternCy(id, x) => self
-                .parent_relm
-                .stream()
-                .emit(SLMControllerMsg::UpdatePatternCy(self.model.id, id, x)),
-            UpdatePatternPhase(id, x) => self
-                .parent_relm
-                .stream()
-                .emit(SLMControllerMsg::UpdatePatternPhase(self.model.id, id, x)),
+                .emit(SLMControllerMsg::UpdatePatternParameter(
+                    self.model.id,
+                    id,
+                    name,
+                    x,
+                )),
             UpdateContainerCx(x) => self
                 .parent_relm
                 .stream()
@@ -162,6 +258,36 @@ impl Update for PatternContainer {
                 .parent_relm
                 .stream()
                 .emit(SLMControllerMsg::UpdateContainerBRY(self.model.id, x)),
+            UpdateContainerCombination(mode) => self.parent_relm.stream().emit(
+                SLMControllerMsg::UpdateContainerCombination(self.model.id, mode),
+            ),
+            SetIlluminated(illuminated) => {
+                self.model.illuminated = illuminated;
+                self.emit_illumination();
+            }
+            UpdateIlluminationWaist(x) => {
+                self.model.illumination.waist = x;
+                self.emit_illumination();
+            }
+            UpdateIlluminationCx(x) => {
+                self.model.illumination.centre.0 = x;
+                self.emit_illumination();
+            }
+            UpdateIlluminationCy(x) => {
+                self.model.illumination.centre.1 = x;
+                self.emit_illumination();
+            }
+            UpdateIlluminationWavelength(x) => {
+                self.model.illumination.wavelength = Some(x).filter(|&x| x > 0.0);
+                self.emit_illumination();
+            }
+            SetIlluminationImage(path) => {
+                if path.is_none() {
+                    self.illumination_image.unselect_all();
+                }
+                self.model.illumination.set_intensity_image(path);
+                self.emit_illumination();
+            }
         }
     }
 }
@@ -180,69 +306,84 @@ impl Widget for PatternContainer {
         let scroll_view = gtk::ScrolledWindow::new::<gtk::Adjustment, _, gtk::Adjustment, _>(None, None);
         let view_port = gtk::Viewport::new::<gtk::Adjustment, _, gtk::Adjustment, _>(None, None);
         let add_pattern_button = gtk::Button::new_with_label("Add pattern");
+        let kind_combo = gtk::ComboBoxText::new();
+        for name in PatternKind::NAMES {
+            kind_combo.append_text(name);
+        }
+        kind_combo.set_active(0);
+        let combination_label = gtk::Label::new("combine by");
+        let combination_combo = gtk::ComboBoxText::new();
+        for name in CombinationMode::NAMES {
+            combination_combo.append_text(name);
+        }
+        let combination_index = CombinationMode::NAMES
+            .iter()
+            .position(|&name| name == model.patterns_data.combination.name())
+            .unwrap_or(0);
+        combination_combo.set_active(combination_index as u32);
         let view_control_box = gtk::Box::new(Orientation::Horizontal, 0);
         let view_control_grid = gtk::Grid::new();
 
         let cx_spin_adjustment = gtk::Adjustment::new(
             model.patterns_data.pos.0,
-            std::f64::MIN,
-            std::f64::MAX,
+            f64::MIN,
+            f64::MAX,
             1.0,
             0.0,
             0.0,
         );
         let cy_spin_adjustment = gtk::Adjustment::new(
             model.patterns_data.pos.1,
-            std::f64::MIN,
-            std::f64::MAX,
+            f64::MIN,
+            f64::MAX,
             1.0,
             0.0,
             0.0,
         );
         let top_left_x_spin_adjustment = gtk::Adjustment::new(
             model.patterns_data.top_left.0,
-            std::f64::MIN,
-            std::f64::MAX,
+            f64::MIN,
+            f64::MAX,
             1.0,
             0.0,
             0.0,
         );
         let top_left_y_spin_adjustment = gtk::Adjustment::new(
             model.patterns_data.top_left.1,
-            std::f64::MIN,
-            std::f64::MAX,
+            f64::MIN,
+            f64::MAX,
             1.0,
             0.0,
             0.0,
         );
         let bottom_right_x_spin_adjustment = gtk::Adjustment::new(
             model.patterns_data.bottom_right.0,
-            std::f64::MIN,
-            std::f64::MAX,
+            f64::MIN,
+            f64::MAX,
             1.0,
             0.0,
             0.0,
         );
         let bottom_right_y_spin_adjustment = gtk::Adjustment::new(
             model.patterns_data.bottom_right.1,
-            std::f64::MIN,
-            std::f64::MAX,
+            f64::MIN,
+            f64::MAX,
             1.0,
             0.0,
             0.0,
         );
         let scalex_spin_adjustment = gtk::Adjustment::new(
             model.patterns_data.scale.0,
-            std::f64::MIN,
-            std::f64::MAX,
+            f64::MIN,
+            f64::MAX,
             1.0,
             0.0,
             0.0,
         );
         let scaley_spin_adjustment = gtk::Adjustment::new(
             model.patterns_data.scale.1,
-            std::f64::MIN,
-            std::f64::MAX,
+            f64::MIN,
+            f64::MAX,
             1.0,
             0.0,
             0.0,
@@ -270,14 +411,105 @@ impl Widget for PatternContainer {
         scalex_spin.set_width_chars(spinner_char_width);
         scaley_spin.set_width_chars(spinner_char_width);
 
+        let illuminated_button = gtk::CheckButton::new_with_label("illuminated");
+        illuminated_button.set_active(model.illuminated);
+        let waist_adjustment =
+            gtk::Adjustment::new(model.illumination.waist, 0.0, f64::MAX, 1.0, 0.0, 0.0);
+        let waist_spin = gtk::SpinButton::new(&waist_adjustment, 0.0, 3);
+        let wavelength_adjustment = gtk::Adjustment::new(
+            model.illumination.wavelength.unwrap_or(0.0),
+            0.0,
+            f64::MAX,
+            1.0,
+            0.0,
+            0.0,
+        );
+        let wavelength_spin = gtk::SpinButton::new(&wavelength_adjustment, 0.0, 1);
+        let beam_x_adjustment = gtk::Adjustment::new(
+            model.illumination.centre.0,
+            f64::MIN,
+            f64::MAX,
+            1.0,
+            0.0,
+            0.0,
+        );
+        let beam_x_spin = gtk::SpinButton::new(&beam_x_adjustment, 0.0, 3);
+        let beam_y_adjustment = gtk::Adjustment::new(
+            model.illumination.centre.1,
+            f64::MIN,
+            f64::MAX,
+            1.0,
+            0.0,
+            0.0,
+        );
+        let beam_y_spin = gtk::SpinButton::new(&beam_y_adjustment, 0.0, 3);
+        let illumination_image =
+            gtk::FileChooserButton::new("Measured intensity", gtk::FileChooserAction::Open);
+        if let Some(ref path) = model.illumination.intensity_image {
+            illumination_image.set_filename(path);
+        }
+        let clear_image_button = gtk::Button::new_with_label("Gaussian");
+        let beam_label = gtk::Label::new("beam waist, λ (nm)");
+        let beam_centre_label = gtk::Label::new("beam centre (x, y)");
+        waist_spin.set_width_chars(spinner_char_width);
+        wavelength_spin.set_width_chars(spinner_char_width);
+        beam_x_spin.set_width_chars(spinner_char_width);
+        beam_y_spin.set_width_chars(spinner_char_width);
+
         pattern_box.set_spacing(10);
+        connect!(relm, add_pattern_button, connect_clicked(_), AddNewPattern);
         connect!(
             relm,
-            add_pattern_button,
-            connect_clicked(_),
-            AddPattern(PatternData{..Default::default()})
+            combination_combo,
+            connect_changed(combo),
+            combo
+                .get_active_text()
+                .and_then(|name| CombinationMode::from_name(name.as_str()))
+                .map(UpdateContainerCombination)
         );
 
+        connect!(
+            relm,
+            illuminated_button,
+            connect_toggled(x),
+            SetIlluminated(x.get_active())
+        );
+        connect!(
+            relm,
+            waist_spin,
+            connect_value_changed(x),
+            UpdateIlluminationWaist(x.get_value())
+        );
+        connect!(
+            relm,
+            wavelength_spin,
+            connect_value_changed(x),
+            UpdateIlluminationWavelength(x.get_value())
+        );
+        connect!(
+            relm,
+            beam_x_spin,
+            connect_value_changed(x),
+            UpdateIlluminationCx(x.get_value())
+        );
+        connect!(
+            relm,
+            beam_y_spin,
+            connect_value_changed(x),
+            UpdateIlluminationCy(x.get_value())
+        );
+        connect!(
+            relm,
+            illumination_image,
+            connect_file_set(x),
+            SetIlluminationImage(x.get_filename())
+        );
+        connect!(
+            relm,
+            clear_image_button,
+            connect_clicked(_),
+            SetIlluminationImage(None)
+        );
         connect!(
             relm,
             cx_spin,
@@ -339,8 +571,20 @@ impl Widget for PatternContainer {
         view_control_grid.attach(&scale_label, 0, 3, 1, 1);
         view_control_grid.attach(&scalex_spin, 1, 3, 1, 1);
         view_control_grid.attach(&scaley_spin, 2, 3, 1, 1);
+        view_control_grid.attach(&combination_label, 0, 4, 1, 1);
+        view_control_grid.attach(&combination_combo, 1, 4, 2, 1);
+        view_control_grid.attach(&beam_label, 0, 5, 1, 1);
+        view_control_grid.attach(&waist_spin, 1, 5, 1, 1);
+        view_control_grid.attach(&wavelength_spin, 2, 5, 1, 1);
+        view_control_grid.attach(&beam_centre_label, 0, 6, 1, 1);
+        view_control_grid.attach(&beam_x_spin, 1, 6, 1, 1);
+        view_control_grid.attach(&beam_y_spin, 2, 6, 1, 1);
+        view_control_grid.attach(&illuminated_button, 0, 7, 1, 1);
+        view_control_grid.attach(&illumination_image, 1, 7, 1, 1);
+        view_control_grid.attach(&clear_image_button, 2, 7, 1, 1);
         view_control_box.pack_start(&view_control_grid, false, false, 0);
         view_control_box.pack_end(&add_pattern_button, false, false, 0);
+        view_control_box.pack_end(&kind_combo, false, false, 0);
 
         view_port.add(&pattern_box);
         scroll_view.add(&view_port);
@@ -351,9 +595,21 @@ impl Widget for PatternContainer {
         let parent_relm = model.parent_relm.clone();
         PatternContainer {
             model,
-            root_box: root_box,
-            pattern_box: pattern_box,
-            parent_relm: parent_relm,
+            root_box,
+            pattern_box,
+            kind_combo,
+            illumination_image,
+            geometry_spins: vec![
+                top_left_x_spin,
+                top_left_y_spin,
+                bottom_right_x_spin,
+                bottom_right_y_spin,
+                cx_spin,
+                cy_spin,
+                scalex_spin,
+                scaley_spin,
+            ],
+            parent_relm,
             patterns: HashMap::new(),
             relm: relm.clone(),
         }
@@ -0,0 +1,150 @@
+//! This module contains phase maps, which give a phase for each pixel of an image.
+//! They are read from greyscale images or numpy arrays, where the level is proportional to the phase
+use image::DynamicImage;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use crate::export;
+use crate::npy::{self, Element};
+
+/// A phase for each pixel of an image, stored row by row
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhaseMap {
+    pub width: usize,
+    pub height: usize,
+    pub phase: Vec<f64>,
+}
+
+impl PhaseMap {
+    /// Load a phase map from an 8 or 16 bit greyscale image.
+    /// The grey level ```two_pi_level``` is a phase of 2π. If it isn't given, the whole range of
+    /// the image is 2π, so 256 for 8 bit images and 65536 for 16 bit images.
+    /// Colour images are converted to greyscale
+    pub fn load_image<P: AsRef<Path>>(path: P, two_pi_level: Option<f64>) -> io::Result<PhaseMap> {
+        let image = image::open(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let (width, height, levels, range) = match image {
+            DynamicImage::ImageLuma16(buffer) => (
+                buffer.width(),
+                buffer.height(),
+                buffer.into_raw().into_iter().map(f64::from).collect::<Vec<_>>(),
+                65536.0,
+            ),
+            DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+            | DynamicImage::ImageRgba16(_) => {
+                let buffer = image.to_luma16();
+                (
+                    buffer.width(),
+                    buffer.height(),
+                    buffer.into_raw().into_iter().map(f64::from).collect(),
+                    65536.0,
+                )
+            }
+            _ => {
+                let buffer = image.to_luma8();
+                (
+                    buffer.width(),
+                    buffer.height(),
+                    buffer.into_raw().into_iter().map(f64::from).collect(),
+                    256.0,
+                )
+            }
+        };
+        let scale = 2.0 * PI / two_pi_level.unwrap_or(range);
+        Ok(PhaseMap {
+            width: width as usize,
+            height: height as usize,
+            phase: levels.into_iter().map(|level| level * scale).collect(),
+        })
+    }
+
+    /// Load a phase map from a ```.npy``` array.
+    /// The value ```two_pi_level``` is a phase of 2π. If it isn't given, integer arrays use their
+    /// whole range (256 for 8 bit, 65536 for 16 bit...), and float arrays are in radians
+    pub fn load_npy<P: AsRef<Path>>(path: P, two_pi_level: Option<f64>) -> io::Result<PhaseMap> {
+        let array = npy::read(BufReader::new(File::open(path)?))?;
+        let (height, width) = match array.shape.as_slice() {
+            [height, width] => (*height, *width),
+            [height, width, 1] => (*height, *width),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "phase map arrays must be 2d",
+                ))
+            }
+        };
+        let range = match array.element {
+            Element::Bool => 2.0,
+            Element::Integer(_, size) => 2f64.powi(8 * size as i32),
+            Element::Float(_) => 2.0 * PI,
+        };
+        let scale = 2.0 * PI / two_pi_level.unwrap_or(range);
+        Ok(PhaseMap {
+            width,
+            height,
+            phase: array.data.into_iter().map(|level| level * scale).collect(),
+        })
+    }
+
+    /// Load a phase map from an image or, if the file ends in ```.npy```, a numpy array
+    pub fn load<P: AsRef<Path>>(path: P, two_pi_level: Option<f64>) -> io::Result<PhaseMap> {
+        let is_npy = path
+            .as_ref()
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("npy"));
+        if is_npy {
+            PhaseMap::load_npy(path, two_pi_level)
+        } else {
+            PhaseMap::load_image(path, two_pi_level)
+        }
+    }
+
+    /// Save the phase map as a 16 bit greyscale image, where the whole range of the image is 2π
+    pub fn save_image<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        export::write_image(path, &self.phase, self.width, self.height)
+    }
+
+    /// The phase at the pixel (x, y), or ```None``` if it is outside of the map
+    pub fn get(&self, x: usize, y: usize) -> Option<f64> {
+        if x < self.width && y < self.height {
+            self.phase.get(y * self.width + x).cloned()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Write a float npy array to a file in the temporary directory
+    fn write_npy(name: &str, shape: &[usize], data: &[f64]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("phase-{}-{}.npy", std::process::id(), name));
+        npy::write_floats(File::create(&path).unwrap(), shape, data, 8).unwrap();
+        path
+    }
+
+    #[test]
+    fn loads_2d_arrays_in_radians() {
+        let path = write_npy("2d", &[2, 3], &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        let map = PhaseMap::load(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((map.width, map.height), (3, 2));
+        assert_eq!(map.get(2, 0), Some(2.0));
+        assert_eq!(map.get(0, 1), Some(3.0));
+        assert_eq!(map.get(3, 0), None);
+    }
+
+    #[test]
+    fn rejects_arrays_which_are_not_2d() {
+        let path = write_npy("3d", &[2, 2, 2], &[0.0; 8]);
+        let error = PhaseMap::load(&path, None).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
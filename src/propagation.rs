@@ -0,0 +1,261 @@
+//! This module simulates the light at any distance after the SLM, with the angular spectrum
+//! method, so the field around the focus of a lens can be seen as well as the far field.
+//! The field is sampled on the SLM's pixels at every distance, and is periodic, so light
+//! leaving one edge of the plane comes back in at the other
+use num_complex::Complex64;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+use crate::device::DeviceProfile;
+use crate::fourier::Fft2;
+use crate::render::wrap_phase;
+use crate::simulation::illuminate;
+
+/// The optics between the SLM and the simulated plane
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Optics {
+    /// The focal length of a thin lens against the SLM, centred on it, in mm.
+    /// Without a lens the light propagates freely
+    pub focal_length: Option<f64>,
+    /// The distance between neighbouring pixels, in μm
+    pub pixel_pitch: f64,
+    /// The wavelength of the light, in nm
+    pub wavelength: f64,
+}
+
+impl Optics {
+    /// The optics for the device, with an optional lens
+    pub fn for_device(device: &DeviceProfile, focal_length: Option<f64>) -> Optics {
+        Optics {
+            focal_length,
+            pixel_pitch: device.pixel_pitch,
+            wavelength: device.wavelength,
+        }
+    }
+}
+
+/// The light in a plane at some distance from the SLM
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plane {
+    pub width: usize,
+    pub height: usize,
+    /// The intensity of each pixel, stored row by row.
+    /// The beam has an amplitude of at most 1 on the SLM, and the total power is kept
+    pub intensity: Vec<f64>,
+    /// The wrapped phase of each pixel, stored row by row
+    pub phase: Vec<f64>,
+}
+
+/// The angular spectrum of the light leaving the SLM, which is propagated to any distance.
+/// Finding the spectrum takes one Fourier transform, and each plane takes one more
+pub struct Propagator {
+    width: usize,
+    height: usize,
+    spectrum: Vec<Complex64>,
+    /// The transverse spatial frequency squared of each point of the spectrum, in μm⁻²
+    frequencies: Vec<f64>,
+    /// One over the wavelength, in μm⁻¹
+    wavenumber: f64,
+    inverse: Fft2,
+}
+
+/// The spatial frequency of index ```i``` of an ```n``` point transform, in cycles per pixel
+fn frequency(i: usize, n: usize) -> f64 {
+    if i < n.div_ceil(2) {
+        i as f64 / n as f64
+    } else {
+        (i as f64 - n as f64) / n as f64
+    }
+}
+
+impl Propagator {
+    /// Find the spectrum of the ```width``` x ```height``` phase, lit with the amplitude
+    /// and passed through the lens, if there is one
+    pub fn new(
+        phase: &[f64],
+        amplitude: &[f64],
+        width: usize,
+        height: usize,
+        optics: &Optics,
+    ) -> Propagator {
+        let pitch = optics.pixel_pitch;
+        let wavelength = optics.wavelength / 1000.0;
+        let mut spectrum = illuminate(phase, amplitude);
+        spectrum.resize(width * height, Complex64::new(0.0, 0.0));
+        if let Some(focal_length) = optics.focal_length.filter(|&f| f != 0.0) {
+            let focal_length = focal_length * 1000.0;
+            for (i, s) in spectrum.iter_mut().enumerate() {
+                let x = ((i % width) as f64 - width as f64 / 2.0) * pitch;
+                let y = ((i / width) as f64 - height as f64 / 2.0) * pitch;
+                *s *= Complex64::from_polar(
+                    &1.0,
+                    &(-PI * (x * x + y * y) / (wavelength * focal_length)),
+                );
+            }
+        }
+        if !spectrum.is_empty() {
+            Fft2::new(width, height, false).process(&mut spectrum);
+        }
+        let frequencies = (0..width * height)
+            .map(|i| {
+                let fx = frequency(i % width, width) / pitch;
+                let fy = frequency(i / width, height) / pitch;
+                fx * fx + fy * fy
+            })
+            .collect();
+        Propagator {
+            width,
+            height,
+            spectrum,
+            frequencies,
+            wavenumber: 1.0 / wavelength,
+            inverse: Fft2::new(width, height, true),
+        }
+    }
+
+    /// The field at ```distance``` mm from the SLM, stored row by row.
+    /// The phase common to the whole plane (2πz/λ) is left out
+    pub fn field(&self, distance: f64) -> Vec<Complex64> {
+        let distance = distance * 1000.0;
+        let wavenumber_squared = self.wavenumber * self.wavenumber;
+        let mut field = self
+            .spectrum
+            .iter()
+            .zip(self.frequencies.iter())
+            .map(|(&s, &f)| {
+                if f >= wavenumber_squared {
+                    // Evanescent waves don't reach the plane
+                    Complex64::new(0.0, 0.0)
+                } else {
+                    // 2πz(√(1/λ² - f²) - 1/λ), written so it is accurate for small f
+                    let delay = -f / (self.wavenumber + (wavenumber_squared - f).sqrt());
+                    s * Complex64::from_polar(&1.0, &(2.0 * PI * distance * delay))
+                }
+            })
+            .collect::<Vec<_>>();
+        if !field.is_empty() {
+            self.inverse.process(&mut field);
+        }
+        field
+    }
+
+    /// The intensity and phase at ```distance``` mm from the SLM
+    pub fn plane(&self, distance: f64) -> Plane {
+        let field = self.field(distance);
+        Plane {
+            width: self.width,
+            height: self.height,
+            intensity: field.iter().map(|f| f.norm_sqr()).collect(),
+            phase: field.iter().map(|f| wrap_phase(f.arg())).collect(),
+        }
+    }
+}
+
+/// The intensity and phase at ```distance``` mm after the SLM, for a
+/// ```width``` x ```height``` phase lit with the amplitude
+pub fn propagate(
+    phase: &[f64],
+    amplitude: &[f64],
+    width: usize,
+    height: usize,
+    optics: &Optics,
+    distance: f64,
+) -> Plane {
+    Propagator::new(phase, amplitude, width, height, optics).plane(distance)
+}
+
+/// The planes at each of the distances (in mm) after the SLM, for scanning through a focus
+pub fn z_scan(
+    phase: &[f64],
+    amplitude: &[f64],
+    width: usize,
+    height: usize,
+    optics: &Optics,
+    distances: &[f64],
+) -> Vec<Plane> {
+    let propagator = Propagator::new(phase, amplitude, width, height, optics);
+    distances.iter().map(|&z| propagator.plane(z)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 24;
+
+    fn optics(focal_length: Option<f64>) -> Optics {
+        Optics {
+            focal_length,
+            pixel_pitch: 8.0,
+            wavelength: 633.0,
+        }
+    }
+
+    /// A phase and amplitude which vary over the SLM
+    fn pattern() -> (Vec<f64>, Vec<f64>) {
+        let phase = (0..WIDTH * HEIGHT)
+            .map(|i| wrap_phase(0.37 * (i % WIDTH) as f64 + 0.011 * (i * i) as f64))
+            .collect();
+        let amplitude = (0..WIDTH * HEIGHT)
+            .map(|i| 0.5 + 0.5 * ((i / WIDTH) as f64 / HEIGHT as f64))
+            .collect();
+        (phase, amplitude)
+    }
+
+    #[test]
+    fn the_field_at_the_slm_is_the_input() {
+        let (phase, amplitude) = pattern();
+        let plane = propagate(&phase, &amplitude, WIDTH, HEIGHT, &optics(None), 0.0);
+        for i in 0..WIDTH * HEIGHT {
+            assert!((plane.intensity[i] - amplitude[i] * amplitude[i]).abs() < 1e-9);
+            let difference = wrap_phase(plane.phase[i] - phase[i]);
+            assert!(difference.min(2.0 * PI - difference) < 1e-6);
+        }
+    }
+
+    #[test]
+    fn power_is_conserved() {
+        // 8 μm pixels sample frequencies well below 1/λ, so nothing is evanescent
+        let (phase, amplitude) = pattern();
+        let power = amplitude.iter().map(|a| a * a).sum::<f64>();
+        for &distance in &[1.0, 50.0, 1000.0] {
+            let plane = propagate(&phase, &amplitude, WIDTH, HEIGHT, &optics(None), distance);
+            let propagated = plane.intensity.iter().sum::<f64>();
+            assert!(
+                (propagated - power).abs() < 1e-9 * power,
+                "at {} mm",
+                distance
+            );
+        }
+    }
+
+    #[test]
+    fn a_lens_focuses_a_plane_wave_at_its_focal_length() {
+        let (width, height) = (64, 64);
+        let phase = vec![0.0; width * height];
+        let amplitude = vec![1.0; width * height];
+        let plane = propagate(&phase, &amplitude, width, height, &optics(Some(20.0)), 20.0);
+        let brightest = (0..width * height)
+            .max_by(|&a, &b| plane.intensity[a].partial_cmp(&plane.intensity[b]).unwrap())
+            .unwrap();
+        assert_eq!(brightest, height / 2 * width + width / 2);
+        let before = propagate(&phase, &amplitude, width, height, &optics(Some(20.0)), 10.0);
+        assert!(plane.intensity[brightest] > 2.0 * before.intensity[brightest]);
+    }
+
+    #[test]
+    fn z_scans_match_propagating_to_each_distance() {
+        let (phase, amplitude) = pattern();
+        let distances = [0.0, 5.0, 20.0, 150.0];
+        let optics = optics(Some(100.0));
+        let planes = z_scan(&phase, &amplitude, WIDTH, HEIGHT, &optics, &distances);
+        assert_eq!(planes.len(), distances.len());
+        for (plane, &distance) in planes.iter().zip(distances.iter()) {
+            assert_eq!(
+                *plane,
+                propagate(&phase, &amplitude, WIDTH, HEIGHT, &optics, distance)
+            );
+        }
+    }
+}
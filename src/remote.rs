@@ -0,0 +1,599 @@
+//! This module lets other programs (experiment control software in python, labview...) control
+//! the patterns over a local TCP connection.
+//! Each command is one line of json, such as ```{"command": "set_parameter", "container": 0,
+//! "pattern": 1, "name": "l", "value": 3}```, and each is answered by one line of json,
+//! ```{"status": "ok"}``` or ```{"status": "error", "message": "..."}```.
+//! Connections which send ```{"command": "subscribe"}``` are also sent events, such as
+//! ```{"event": "frame", "index": 3, "parameters": [["0.l", -7.0]]}``` when a frame of a
+//! sequence is presented.
+//! Anyone who can connect can load any file the controller can read, and set every pattern, so
+//! the server should only listen on addresses which trusted programs use, such as 127.0.0.1
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::pattern::{Pattern, PatternKind};
+use crate::slm_data::{PatternContainerData, SLMData};
+
+/// A command for the controller
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Add a container, which covers the whole device if none is given.
+    /// The reply gives the id of the new container
+    AddContainer {
+        #[serde(default)]
+        container: Option<PatternContainerData>,
+    },
+    RemoveContainer {
+        container: usize,
+    },
+    RemoveAllContainers,
+    /// Add a pattern to a container. The reply gives the id of the new pattern
+    AddPattern {
+        container: usize,
+        pattern: PatternKind,
+    },
+    RemovePattern {
+        container: usize,
+        pattern: usize,
+    },
+    /// Set a parameter of a pattern, named as in the GUI (```l```, ```a```, ```k.0```,
+    /// ```k.1```, ```c.0```, ```c.1```, ```phase```...)
+    SetParameter {
+        container: usize,
+        pattern: usize,
+        name: String,
+        value: f64,
+    },
+    /// Set the crop rectangle of a container
+    SetCrop {
+        container: usize,
+        top_left: (f64, f64),
+        bottom_right: (f64, f64),
+    },
+    SetPos {
+        container: usize,
+        pos: (f64, f64),
+    },
+    SetScale {
+        container: usize,
+        scale: (f64, f64),
+    },
+    /// Replace the device, containers and correction with those in a file saved from the GUI.
+    /// Nothing is replaced if the file has an image pattern whose map can't be read.
+    /// The path is opened by the controller, so it is relative to the controller's directory
+    /// and can be any file the controller can read
+    LoadFile {
+        path: PathBuf,
+    },
+    /// Render the patterns and display them
+    Render,
+    /// Reply with the device, containers and correction
+    GetData,
+    /// Send events to this connection, as well as replies
+    Subscribe,
+    /// Close the controller, after replying
+    Quit,
+}
+
+/// The reply to a command
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Reply {
+    /// The command was carried out. Commands which add something give its id
+    Ok {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<usize>,
+    },
+    /// The reply to ```get_data```
+    Data { data: Box<SLMData> },
+    Error { message: String },
+}
+
+impl Reply {
+    pub fn ok() -> Reply {
+        Reply::Ok { id: None }
+    }
+
+    pub fn error<S: ToString>(message: S) -> Reply {
+        Reply::Error {
+            message: message.to_string(),
+        }
+    }
+
+    /// Read a reply from json.
+    /// The data is read on its own, as serde can't read the numbered containers of an
+    /// internally tagged variant
+    pub fn from_json(text: &str) -> serde_json::Result<Reply> {
+        let mut value: serde_json::Value = serde_json::from_str(text)?;
+        if value.get("status").and_then(|s| s.as_str()) == Some("data") {
+            let data = value
+                .get_mut("data")
+                .map(serde_json::Value::take)
+                .unwrap_or_default();
+            Ok(Reply::Data {
+                data: Box::new(serde_json::from_value(data)?),
+            })
+        } else {
+            serde_json::from_value(value)
+        }
+    }
+}
+
+/// Something which happened in the controller, sent to the connections which subscribed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A frame of a sequence has been drawn in the output window
+    Frame {
+        index: usize,
+        /// The values of the fields swept for the frame, labelled as in the sequence
+        parameters: Vec<(String, f64)>,
+    },
+}
+
+/// The number of events queued for a subscriber which isn't reading them, before it is
+/// disconnected
+const EVENT_QUEUE_LENGTH: usize = 256;
+
+/// The lines of json waiting to be written to a subscriber
+type EventQueue = mpsc::SyncSender<Arc<Vec<u8>>>;
+
+/// The connections which subscribed to events. Clones share the same connections
+#[derive(Clone, Default)]
+pub struct Events {
+    /// The queue of events for each subscriber, which are written by a thread of its own
+    subscribers: Arc<Mutex<Vec<EventQueue>>>,
+}
+
+impl Events {
+    /// Start writing events to a connection, on a thread of its own so a connection which
+    /// isn't read can't hold up the controller
+    fn subscribe(&self, writer: Arc<Mutex<TcpStream>>) {
+        let (sender, receiver) = mpsc::sync_channel::<Arc<Vec<u8>>>(EVENT_QUEUE_LENGTH);
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        thread::spawn(move || {
+            for line in receiver {
+                let written = writer
+                    .lock()
+                    .map(|mut writer| writer.write_all(&line).is_ok())
+                    .unwrap_or(false);
+                if !written {
+                    break;
+                }
+            }
+            // the connection is closed when it falls behind, rather than skipping events
+            if let Ok(writer) = writer.lock() {
+                let _ = writer.shutdown(Shutdown::Both);
+            }
+        });
+    }
+
+    /// Queue the event for every subscribed connection, without waiting for it to be written.
+    /// Connections which have closed, or which have fallen too far behind, are forgotten
+    pub fn send(&self, event: &Event) {
+        let mut line = match serde_json::to_vec(event) {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        line.push(b'\n');
+        let line = Arc::new(line);
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|sender| sender.try_send(line.clone()).is_ok());
+        }
+    }
+}
+
+/// The next unused id in a map
+fn next_id<T>(map: &HashMap<usize, T>) -> usize {
+    map.keys().max().map_or(0, |id| id + 1)
+}
+
+/// Find a container, or give the error for the reply
+fn container_mut(data: &mut SLMData, id: usize) -> Result<&mut PatternContainerData, String> {
+    data.containers
+        .get_mut(&id)
+        .ok_or_else(|| format!("there is no container {}", id))
+}
+
+/// Carry out a command on the data, giving the reply. New containers and patterns are given
+/// the next id after the largest in use. Rendering is left to the caller
+pub fn apply(command: &Command, data: &mut SLMData) -> Reply {
+    let result = match command {
+        Command::AddContainer { container } => {
+            let id = next_id(&data.containers);
+            let container = container
+                .clone()
+                .unwrap_or_else(|| PatternContainerData::for_device(&data.device));
+            data.containers.insert(id, container);
+            Ok(Reply::Ok { id: Some(id) })
+        }
+        Command::RemoveContainer { container } => data
+            .containers
+            .remove(container)
+            .map(|_| Reply::ok())
+            .ok_or_else(|| format!("there is no container {}", container)),
+        Command::RemoveAllContainers => {
+            data.containers.clear();
+            Ok(Reply::ok())
+        }
+        Command::AddPattern { container, pattern } => {
+            let readable = match pattern {
+                PatternKind::Image(image) => image.load().map(|_| ()),
+                _ => Ok(()),
+            };
+            readable
+                .and_then(|_| container_mut(data, *container))
+                .map(|container| {
+                    let id = next_id(&container.patterns);
+                    container.patterns.insert(id, pattern.clone());
+                    Reply::Ok { id: Some(id) }
+                })
+        }
+        Command::RemovePattern { container, pattern } => {
+            container_mut(data, *container).and_then(|container| {
+                container
+                    .patterns
+                    .remove(pattern)
+                    .map(|_| Reply::ok())
+                    .ok_or_else(|| format!("there is no pattern {}", pattern))
+            })
+        }
+        Command::SetParameter {
+            container,
+            pattern,
+            name,
+            value,
+        } => container_mut(data, *container).and_then(|container| {
+            let data = container
+                .patterns
+                .get_mut(pattern)
+                .ok_or_else(|| format!("there is no pattern {}", pattern))?;
+            if data.set_parameter(name, *value) {
+                Ok(Reply::ok())
+            } else {
+                Err(format!("{} patterns have no parameter {}", data.name(), name))
+            }
+        }),
+        Command::SetCrop {
+            container,
+            top_left,
+            bottom_right,
+        } => container_mut(data, *container).map(|container| {
+            container.top_left = *top_left;
+            container.bottom_right = *bottom_right;
+            Reply::ok()
+        }),
+        Command::SetPos { container, pos } => container_mut(data, *container).map(|container| {
+            container.pos = *pos;
+            Reply::ok()
+        }),
+        Command::SetScale { container, scale } => {
+            container_mut(data, *container).map(|container| {
+                container.scale = *scale;
+                Reply::ok()
+            })
+        }
+        Command::LoadFile { path } => File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| SLMData::from_reader(file).map_err(|e| e.to_string()))
+            .and_then(|loaded| match loaded.image_errors().as_slice() {
+                [] => Ok(loaded),
+                errors => Err(errors.join("; ")),
+            })
+            .map(|loaded| {
+                *data = loaded;
+                Reply::ok()
+            })
+            .map_err(|e| format!("couldn't load {}: {}", path.display(), e)),
+        Command::Render | Command::Subscribe | Command::Quit => Ok(Reply::ok()),
+        Command::GetData => Ok(Reply::Data {
+            data: Box::new(data.clone()),
+        }),
+    };
+    result.unwrap_or_else(Reply::error)
+}
+
+/// Answer the commands on one connection, until it is closed
+fn handle_connection<F>(stream: TcpStream, events: Events, handle: F) -> io::Result<()>
+where
+    F: Fn(Command, mpsc::Sender<Reply>),
+{
+    // the writer is shared with the events, so replies and events aren't mixed up
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Command>(&line) {
+            Ok(Command::Subscribe) => {
+                events.subscribe(writer.clone());
+                Reply::ok()
+            }
+            Ok(command) => {
+                let (sender, receiver) = mpsc::channel();
+                handle(command, sender);
+                receiver
+                    .recv()
+                    .unwrap_or_else(|_| Reply::error("the controller has stopped"))
+            }
+            Err(e) => Reply::error(format!("couldn't read the command: {}", e)),
+        };
+        let mut line = serde_json::to_vec(&reply)?;
+        line.push(b'\n');
+        writer
+            .lock()
+            .map_err(|_| io::Error::other("the connection's writer was poisoned"))?
+            .write_all(&line)?;
+    }
+    Ok(())
+}
+
+/// Listen for connections on the address, answering them on other threads.
+/// Each command received is passed to ```handle``` with a sender for its reply, and
+/// commands on a connection are answered in order. Events sent with ```events``` go to the
+/// connections which subscribed.
+/// Returns the address which is listened on, so port 0 can be used to pick any free port
+pub fn serve<A, F>(address: A, events: Events, handle: F) -> io::Result<SocketAddr>
+where
+    A: ToSocketAddrs,
+    F: Fn(Command, mpsc::Sender<Reply>) + Clone + Send + 'static,
+{
+    let listener = TcpListener::bind(address)?;
+    let local_address = listener.local_addr()?;
+    serve_on(listener, events, handle);
+    Ok(local_address)
+}
+
+/// Answer the connections to a listener which is already bound, as ```serve``` does.
+/// Binding first lets a program report an address which can't be listened on before it starts
+pub fn serve_on<F>(listener: TcpListener, events: Events, handle: F)
+where
+    F: Fn(Command, mpsc::Sender<Reply>) + Clone + Send + 'static,
+{
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let handle = handle.clone();
+            let events = events.clone();
+            thread::spawn(move || handle_connection(stream, events, handle));
+        }
+    });
+}
+
+/// A connection to a controller, for sending it commands
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    /// Events which arrived while waiting for a reply
+    events: VecDeque<Event>,
+}
+
+impl Client {
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Client> {
+        let writer = TcpStream::connect(address)?;
+        Ok(Client {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            events: VecDeque::new(),
+        })
+    }
+
+    /// Read the next line from the controller, which is a reply or an event
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the controller closed the connection",
+            ));
+        }
+        Ok(line)
+    }
+
+    /// Send a command, and wait for its reply.
+    /// Events which arrive first are kept for ```next_event```
+    pub fn send(&mut self, command: &Command) -> io::Result<Reply> {
+        serde_json::to_writer(&mut self.writer, command)?;
+        self.writer.write_all(b"\n")?;
+        loop {
+            let line = self.read_line()?;
+            match serde_json::from_str::<Event>(&line) {
+                Ok(event) => self.events.push_back(event),
+                Err(_) => return Reply::from_json(&line).map_err(io::Error::from),
+            }
+        }
+    }
+
+    /// Wait for the next event. Only connections which sent ```subscribe``` are sent events
+    pub fn next_event(&mut self) -> io::Result<Event> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        let line = self.read_line()?;
+        serde_json::from_str(&line).map_err(io::Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::DeviceProfile;
+
+    /// Serve on any free port, applying the commands to shared data
+    fn serve_data() -> (SocketAddr, Arc<Mutex<SLMData>>, Events) {
+        let data = Arc::new(Mutex::new(SLMData::default()));
+        let events = Events::default();
+        let shared = data.clone();
+        let address = serve("127.0.0.1:0", events.clone(), move |command, sender| {
+            let reply = apply(&command, &mut shared.lock().unwrap());
+            let _ = sender.send(reply);
+        })
+        .unwrap();
+        (address, data, events)
+    }
+
+    fn vortex() -> PatternKind {
+        PatternKind::new("vortex").unwrap()
+    }
+
+    fn id(reply: Reply) -> usize {
+        match reply {
+            Reply::Ok { id: Some(id) } => id,
+            reply => panic!("expected an id, got {:?}", reply),
+        }
+    }
+
+    fn message(reply: Reply) -> String {
+        match reply {
+            Reply::Error { message } => message,
+            reply => panic!("expected an error, got {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn apply_gives_the_next_ids() {
+        let mut data = SLMData::default();
+        let add = Command::AddContainer { container: None };
+        assert_eq!(id(apply(&add, &mut data)), 0);
+        assert_eq!(id(apply(&add, &mut data)), 1);
+        let remove = Command::RemoveContainer { container: 0 };
+        assert!(matches!(apply(&remove, &mut data), Reply::Ok { id: None }));
+        assert_eq!(id(apply(&add, &mut data)), 2);
+        let pattern = Command::AddPattern {
+            container: 2,
+            pattern: vortex(),
+        };
+        assert_eq!(id(apply(&pattern, &mut data)), 0);
+        assert_eq!(id(apply(&pattern, &mut data)), 1);
+        assert_eq!(data.containers[&2].patterns.len(), 2);
+        assert_eq!(
+            data.containers[&1].bottom_right,
+            PatternContainerData::for_device(&DeviceProfile::default()).bottom_right
+        );
+    }
+
+    #[test]
+    fn apply_reports_missing_containers_patterns_and_parameters() {
+        let mut data = SLMData::default();
+        apply(&Command::AddContainer { container: None }, &mut data);
+        let set = |container, pattern, name: &str| Command::SetParameter {
+            container,
+            pattern,
+            name: name.to_string(),
+            value: 1.0,
+        };
+        assert_eq!(
+            message(apply(&set(3, 0, "l"), &mut data)),
+            "there is no container 3"
+        );
+        assert_eq!(
+            message(apply(&set(0, 0, "l"), &mut data)),
+            "there is no pattern 0"
+        );
+        apply(
+            &Command::AddPattern {
+                container: 0,
+                pattern: vortex(),
+            },
+            &mut data,
+        );
+        assert!(message(apply(&set(0, 0, "waist"), &mut data)).contains("no parameter waist"));
+        let missing = Command::LoadFile {
+            path: PathBuf::from("/nonexistent/patterns.json"),
+        };
+        assert!(message(apply(&missing, &mut data)).starts_with("couldn't load"));
+        assert_eq!(data.containers[&0].patterns.len(), 1);
+    }
+
+    #[test]
+    fn serves_commands_over_tcp() {
+        let (address, data, _) = serve_data();
+        let mut client = Client::connect(address).unwrap();
+        let container = id(client
+            .send(&Command::AddContainer { container: None })
+            .unwrap());
+        let pattern = id(client
+            .send(&Command::AddPattern {
+                container,
+                pattern: vortex(),
+            })
+            .unwrap());
+        let reply = client
+            .send(&Command::SetParameter {
+                container,
+                pattern,
+                name: "l".to_string(),
+                value: 3.0,
+            })
+            .unwrap();
+        assert!(matches!(reply, Reply::Ok { id: None }));
+        match &data.lock().unwrap().containers[&container].patterns[&pattern] {
+            PatternKind::Vortex(vortex) => assert_eq!(vortex.l, 3),
+            pattern => panic!("expected a vortex, got {:?}", pattern),
+        }
+
+        let reply = client
+            .send(&Command::RemovePattern {
+                container: 7,
+                pattern: 0,
+            })
+            .unwrap();
+        assert_eq!(message(reply), "there is no container 7");
+
+        match client.send(&Command::GetData).unwrap() {
+            Reply::Data { data } => assert_eq!(data.containers.len(), 1),
+            reply => panic!("expected the data, got {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn answers_malformed_commands_with_errors() {
+        let (address, _, _) = serve_data();
+        let mut client = Client::connect(address).unwrap();
+        client
+            .writer
+            .write_all(b"{\"command\": \"add_container\"\n")
+            .unwrap();
+        assert!(
+            message(Reply::from_json(&client.read_line().unwrap()).unwrap())
+                .starts_with("couldn't read the command")
+        );
+        client
+            .writer
+            .write_all(b"{\"command\": \"fly\"}\n")
+            .unwrap();
+        assert!(
+            message(Reply::from_json(&client.read_line().unwrap()).unwrap())
+                .starts_with("couldn't read the command")
+        );
+        // the connection is still answered afterwards
+        let reply = client.send(&Command::Render).unwrap();
+        assert!(matches!(reply, Reply::Ok { id: None }));
+    }
+
+    #[test]
+    fn sends_events_to_subscribers() {
+        let (address, _, events) = serve_data();
+        let mut subscriber = Client::connect(address).unwrap();
+        let mut other = Client::connect(address).unwrap();
+        subscriber.send(&Command::Subscribe).unwrap();
+        let frame = Event::Frame {
+            index: 3,
+            parameters: vec![("0.l".to_string(), -7.0)],
+        };
+        events.send(&frame);
+        assert_eq!(subscriber.next_event().unwrap(), frame);
+        // the other connection is only sent replies
+        other.send(&Command::Render).unwrap();
+        assert!(other.events.is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+//! This module turns the pattern data into the phase pattern which is displayed on the SLM
+use num_complex::Complex64;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use crate::device::Calibration;
+use crate::pattern::{Pattern, PatternKind};
+use crate::slm_data::*;
+
+/// A hash of the screen pixel (x, y), used to choose a pattern for the pixel when
+/// multiplexing. This is the splitmix64 finaliser, so neighbouring pixels are unrelated
+fn pixel_hash(x: f64, y: f64) -> u64 {
+    let mut z = ((x as i64 as u64) << 32) ^ (y as i64 as u64);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A container being rendered, with its patterns in order of id and the peak of each pattern
+/// found once for the whole render
+struct PreparedContainer<'a> {
+    container: &'a PatternContainerData,
+    patterns: Vec<(&'a PatternKind, f64)>,
+}
+
+impl<'a> PreparedContainer<'a> {
+    fn new(container: &'a PatternContainerData) -> PreparedContainer<'a> {
+        let mut ids = container.patterns.keys().collect::<Vec<_>>();
+        ids.sort();
+        PreparedContainer {
+            container,
+            patterns: ids
+                .into_iter()
+                .map(|id| {
+                    let pattern = &container.patterns[id];
+                    (pattern, pattern.peak())
+                })
+                .collect(),
+        }
+    }
+
+    /// The field of the container at the screen pixel (x, y), as in ```container_field```
+    fn field(&self, x: f64, y: f64) -> Option<Complex64> {
+        let container = self.container;
+        if !container.contains(x, y) {
+            return None;
+        }
+        let u = (x - container.pos.0) / container.scale.0;
+        let v = (y - container.pos.1) / container.scale.1;
+        let illumination = container
+            .illumination
+            .as_ref()
+            .map_or(1.0, |i| i.amplitude(x - container.pos.0, y - container.pos.1));
+        let zero = Complex64::new(0.0, 0.0);
+        Some(match container.combination {
+            CombinationMode::ComplexSuperposition => self
+                .patterns
+                .iter()
+                .map(|(pattern, peak)| pattern.peak_field(u, v, illumination, *peak))
+                .sum(),
+            CombinationMode::SumPhases => {
+                // only the phases are added, so a pattern with no field at the pixel (such as
+                // an image outside of its map) adds nothing rather than blanking the others.
+                // Patterns with an amplitude of 0 are left out, and the sign of the amplitude
+                // doesn't change the phase
+                let shown = self
+                    .patterns
+                    .iter()
+                    .filter(|(pattern, _)| pattern.amplitude() != 0.0)
+                    .map(|(pattern, peak)| {
+                        let sign = pattern.amplitude().signum();
+                        (pattern.peak_field(u, v, illumination, *peak) * sign).arg()
+                    })
+                    .collect::<Vec<_>>();
+                if shown.is_empty() {
+                    zero
+                } else {
+                    Complex64::from_polar(&1.0, &shown.iter().sum())
+                }
+            }
+            CombinationMode::RandomMultiplex => {
+                if self.patterns.is_empty() {
+                    zero
+                } else {
+                    let (pattern, peak) =
+                        self.patterns[(pixel_hash(x, y) % self.patterns.len() as u64) as usize];
+                    pattern.peak_field(u, v, illumination, peak)
+                }
+            }
+        })
+    }
+}
+
+/// The complex field of a container at the screen pixel (x, y), with the patterns combined
+/// by the container's combination mode. Amplitude encoded patterns are corrected for the
+/// container's illumination.
+/// Returns ```None``` if the pixel is outside of the container's crop rectangle
+pub fn container_field(container: &PatternContainerData, x: f64, y: f64) -> Option<Complex64> {
+    PreparedContainer::new(container).field(x, y)
+}
+
+/// Wrap a phase into the range [0, 2π)
+pub fn wrap_phase(phase: f64) -> f64 {
+    let wrapped = phase.rem_euclid(2.0 * PI);
+    if wrapped >= 2.0 * PI {
+        0.0
+    } else {
+        wrapped
+    }
+}
+
+/// Convert a wrapped phase into an 8 bit grey level
+pub fn phase_to_grey(phase: f64) -> u8 {
+    (wrap_phase(phase) / (2.0 * PI) * 256.0).min(255.0) as u8
+}
+
+/// Calculate the wrapped phase of every pixel on a ```width``` x ```height``` screen.
+/// The fields from every pattern in every container are summed, and the argument is taken.
+/// The result is stored row by row.
+pub fn render_phase(
+    containers: &HashMap<usize, PatternContainerData>,
+    width: usize,
+    height: usize,
+) -> Vec<f64> {
+    let mut ids = containers.keys().collect::<Vec<_>>();
+    ids.sort();
+    let containers = ids
+        .iter()
+        .map(|id| PreparedContainer::new(&containers[id]))
+        .collect::<Vec<_>>();
+    let mut phase = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let field: Complex64 = containers
+                .iter()
+                .filter_map(|container| container.field(x as f64, y as f64))
+                .sum();
+            phase.push(wrap_phase(field.arg()));
+        }
+    }
+    phase
+}
+
+/// Render the containers into an 8 bit greyscale image, with one byte per pixel
+pub fn render(
+    containers: &HashMap<usize, PatternContainerData>,
+    width: usize,
+    height: usize,
+) -> Vec<u8> {
+    render_phase(containers, width, height)
+        .into_iter()
+        .map(phase_to_grey)
+        .collect()
+}
+
+/// Calculate the phase of every pixel of the device, before it is wrapped.
+/// This is the wrapped phase of the containers with the aberration correction
+/// and the device's flatness correction added
+pub fn render_device_phase_unwrapped(data: &SLMData, calibration: &Calibration) -> Vec<f64> {
+    let (width, height) = (data.device.width, data.device.height);
+    let mut phase = render_phase(&data.containers, width, height);
+    if !data.correction.is_zero() {
+        for (i, p) in phase.iter_mut().enumerate() {
+            let (x, y) = ((i % width) as f64, (i / width) as f64);
+            *p += data.correction.phase(x, y);
+        }
+    }
+    if let Some(ref correction) = calibration.correction {
+        for (i, p) in phase.iter_mut().enumerate() {
+            if let Some(c) = correction.get(i % width, i / width) {
+                *p += c;
+            }
+        }
+    }
+    phase
+}
+
+/// Calculate the wrapped phase of every pixel of the device.
+/// This is the phase of the containers with the aberration correction
+/// and the device's flatness correction added
+pub fn render_device_phase(data: &SLMData, calibration: &Calibration) -> Vec<f64> {
+    render_device_phase_unwrapped(data, calibration)
+        .into_iter()
+        .map(wrap_phase)
+        .collect()
+}
+
+/// Render the data into the grey levels of the device, at the device's resolution.
+/// The calibration is applied when the phase is converted to grey levels
+pub fn render_levels(data: &SLMData, calibration: &Calibration) -> Vec<u16> {
+    render_device_phase(data, calibration)
+        .into_iter()
+        .map(|phase| calibration.phase_to_level(&data.device, phase))
+        .collect()
+}
+
+/// Convert a wrapped phase rendered for the device into the 8 bit greyscale image
+/// which is displayed, with one byte per pixel
+pub fn device_grey(data: &SLMData, calibration: &Calibration, phase: &[f64]) -> Vec<u8> {
+    phase
+        .iter()
+        .map(|&phase| {
+            data.device
+                .level_to_grey(calibration.phase_to_level(&data.device, phase))
+        })
+        .collect()
+}
+
+/// Render the data for the device into an 8 bit greyscale image, with one byte per pixel
+pub fn render_device(data: &SLMData, calibration: &Calibration) -> Vec<u8> {
+    device_grey(data, calibration, &render_device_phase(data, calibration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vortex(a: f64, phase: f64) -> PatternKind {
+        PatternKind::Vortex(PatternData {
+            a,
+            phase,
+            ..Default::default()
+        })
+    }
+
+    fn sum_phases(patterns: Vec<PatternKind>) -> PatternContainerData {
+        PatternContainerData {
+            bottom_right: (10.0, 10.0),
+            scale: (1.0, 1.0),
+            patterns: patterns.into_iter().enumerate().collect(),
+            combination: CombinationMode::SumPhases,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sum_phases_adds_the_phases() {
+        let container = sum_phases(vec![vortex(1.0, 0.5), vortex(0.3, 0.7)]);
+        let field = container_field(&container, 2.0, 3.0).unwrap();
+        assert!((field.arg() - 1.2).abs() < 1e-12);
+        assert!((field.norm() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn sum_phases_ignores_the_amplitude() {
+        let container = sum_phases(vec![vortex(-1.0, 0.5), vortex(0.0, 2.0), vortex(1.0, 0.25)]);
+        let field = container_field(&container, 2.0, 3.0).unwrap();
+        assert!((field.arg() - 0.75).abs() < 1e-12);
+    }
+
+    #[test]
+    fn sum_phases_keeps_patterns_where_another_has_no_field() {
+        // an image with no map has no field anywhere
+        let image = PatternKind::Image(ImageData {
+            a: 1.0,
+            ..Default::default()
+        });
+        let container = sum_phases(vec![vortex(1.0, 0.5), image]);
+        let field = container_field(&container, 2.0, 3.0).unwrap();
+        assert!((field.arg() - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn pixels_outside_the_crop_have_no_field() {
+        let container = sum_phases(vec![vortex(1.0, 0.5)]);
+        assert_eq!(container_field(&container, 10.0, 3.0), None);
+    }
+}
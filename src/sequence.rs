@@ -0,0 +1,535 @@
+//! This module steps through a list of holograms, such as a scan of ```l``` from -10 to 10.
+//! A sequence is an ordered list of steps, each of which is a snapshot of all the containers
+//! or a sweep of one or two fields of the patterns or containers. The steps are expanded into
+//! frames, which are each shown for a dwell time and can be rendered before they are played,
+//! or exported as images named by the values of the swept fields.
+//! Sequences are saved as json
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::device::Calibration;
+use crate::export::{self, Format};
+use crate::pattern::Pattern;
+use crate::render;
+use crate::slm_data::{PatternContainerData, SLMData};
+
+/// The most frames a sequence can have, so a mistyped step such as ```0:1000:1e-9``` is
+/// rejected rather than filling the memory
+pub const MAX_FRAMES: usize = 100_000;
+
+/// The number of decimal places the values of a swept field are rounded to, so steps such as
+/// 0.1 don't give values such as 0.30000000000000004
+const VALUE_DECIMALS: i32 = 9;
+
+/// Round a value of a swept field to ```VALUE_DECIMALS``` decimal places
+fn round_value(value: f64) -> f64 {
+    let scale = 10f64.powi(VALUE_DECIMALS);
+    (value * scale).round() / scale
+}
+
+/// Write a value of a swept field with at most 6 decimal places and no trailing zeros,
+/// as in ```0.3``` or ```-7```
+fn format_value(value: f64) -> String {
+    let text = format!("{:.6}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text == "-0" {
+        String::from("0")
+    } else {
+        text.to_string()
+    }
+}
+
+/// All of the containers, shown as one frame
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub containers: HashMap<usize, PatternContainerData>,
+    /// How long the frame is shown for in ms, instead of the sequence's dwell time
+    #[serde(default)]
+    pub dwell: Option<f64>,
+}
+
+/// A field swept by a sweep: a parameter of a pattern, or the position or scale of a container,
+/// with the values it takes
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Axis {
+    pub container: usize,
+    /// The pattern whose parameter is swept. Without one the field is the container's
+    /// (```pos.0```, ```pos.1```, ```scale.0``` or ```scale.1```)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<usize>,
+    /// The field, named as in the GUI (```l```, ```a```, ```k.0```...)
+    pub name: String,
+    #[serde(default)]
+    pub start: f64,
+    #[serde(default)]
+    pub stop: f64,
+    #[serde(default)]
+    pub step: f64,
+    /// The values to take, instead of ```start```, ```stop``` and ```step```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<f64>>,
+}
+
+impl Axis {
+    /// Read an axis of the container from text such as ```0.l = -10:10:1``` (pattern 0's ```l```
+    /// from -10 to 10 in steps of 1) or ```pos.0 = 0, 50, 200``` (the container's x position
+    /// taking each value in the list)
+    pub fn parse(container: usize, text: &str) -> Result<Axis, String> {
+        let mut sides = text.splitn(2, '=');
+        let field = sides.next().unwrap_or_default().trim();
+        let values = sides
+            .next()
+            .ok_or_else(|| format!("{} has no values, as in l = 0:10:1", text.trim()))?
+            .trim();
+        let (pattern, name) = match field.split_once('.') {
+            Some((pattern, name)) if pattern.parse::<usize>().is_ok() => {
+                (pattern.parse::<usize>().ok(), name)
+            }
+            _ => (None, field),
+        };
+        if pattern.is_none() && !PatternContainerData::PARAMETERS.contains(&name) {
+            return Err(format!(
+                "{} isn't a field of the container, so give its pattern, as in 0.{}",
+                name, name
+            ));
+        }
+        let number = |text: &str| {
+            text.trim()
+                .parse::<f64>()
+                .map_err(|_| format!("{} isn't a number", text.trim()))
+        };
+        let mut axis = Axis {
+            container,
+            pattern,
+            name: name.to_string(),
+            start: 0.0,
+            stop: 0.0,
+            step: 0.0,
+            values: None,
+        };
+        let range = values.split(':').collect::<Vec<_>>();
+        if range.len() == 3 {
+            axis.start = number(range[0])?;
+            axis.stop = number(range[1])?;
+            axis.step = number(range[2])?;
+        } else {
+            axis.values = Some(
+                values
+                    .split(',')
+                    .map(number)
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+        }
+        if axis.count() > MAX_FRAMES as f64 {
+            return Err(format!(
+                "{} gives {} values, but a sequence can't have more than {} frames",
+                text.trim(),
+                axis.count(),
+                MAX_FRAMES
+            ));
+        }
+        Ok(axis)
+    }
+
+    /// The number of values of the field. This is a float, so a range with a tiny step gives
+    /// a huge count rather than overflowing
+    pub fn count(&self) -> f64 {
+        if let Some(values) = &self.values {
+            return values.len() as f64;
+        }
+        let steps = (self.stop - self.start) / self.step;
+        if !steps.is_finite() || steps < 0.0 {
+            return 1.0;
+        }
+        // allow for rounding, so the stop is included when the steps fit exactly
+        (steps + 1e-9).floor() + 1.0
+    }
+
+    /// The values of the field: the list if there is one, otherwise from ```start``` in steps
+    /// up to and including ```stop```. A step of 0, or one away from ```stop```, gives only
+    /// ```start```. Each value is counted from ```start``` in whole steps and rounded, so
+    /// errors don't build up over the steps. There are at most ```MAX_FRAMES``` values
+    pub fn values(&self) -> Vec<f64> {
+        if let Some(values) = &self.values {
+            return values.clone();
+        }
+        let count = self.count();
+        if count <= 1.0 {
+            return vec![self.start];
+        }
+        (0..count.min(MAX_FRAMES as f64) as usize)
+            .map(|i| round_value(self.start + i as f64 * self.step))
+            .collect()
+    }
+
+    /// The name of the field, with its pattern if it is a pattern's parameter
+    pub fn label(&self) -> String {
+        match self.pattern {
+            Some(pattern) => format!("{}.{}", pattern, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Set the field to the value in the containers
+    pub fn apply(
+        &self,
+        containers: &mut HashMap<usize, PatternContainerData>,
+        value: f64,
+    ) -> Result<(), String> {
+        let container = containers
+            .get_mut(&self.container)
+            .ok_or_else(|| format!("there is no container {}", self.container))?;
+        match self.pattern {
+            Some(pattern) => {
+                let pattern = container
+                    .patterns
+                    .get_mut(&pattern)
+                    .ok_or_else(|| format!("there is no pattern {}", pattern))?;
+                if pattern.set_parameter(&self.name, value) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "{} patterns have no parameter {}",
+                        pattern.name(),
+                        self.name
+                    ))
+                }
+            }
+            None if container.set_parameter(&self.name, value) => Ok(()),
+            None => Err(format!("containers have no field {}", self.name)),
+        }
+    }
+}
+
+/// A sweep of one field, or of two nested fields, with a frame for each value.
+/// The fields are named directly in the sweep, as in
+/// ```{"container": 0, "pattern": 0, "name": "l", "start": -10, "stop": 10, "step": 1}```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Sweep {
+    #[serde(flatten)]
+    pub axis: Axis,
+    /// A second field which is swept through all of its values for each value of the first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inner: Option<Axis>,
+    /// How long each frame is shown for in ms, instead of the sequence's dwell time
+    #[serde(default)]
+    pub dwell: Option<f64>,
+}
+
+impl Sweep {
+    /// The number of frames of the sweep
+    pub fn count(&self) -> f64 {
+        self.axis.count() * self.inner.as_ref().map_or(1.0, Axis::count)
+    }
+
+    /// The values of the fields for each frame, in order. The inner field changes fastest
+    pub fn values(&self) -> Vec<Vec<(&Axis, f64)>> {
+        let axes = std::iter::once(&self.axis)
+            .chain(self.inner.as_ref())
+            .collect::<Vec<_>>();
+        let mut frames = vec![vec![]];
+        for axis in axes {
+            let values = axis.values();
+            frames = frames
+                .into_iter()
+                .flat_map(|frame: Vec<(&Axis, f64)>| {
+                    values.iter().map(move |&value| {
+                        let mut frame = frame.clone();
+                        frame.push((axis, value));
+                        frame
+                    })
+                })
+                .collect();
+        }
+        frames
+    }
+}
+
+/// A step of a sequence
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    Snapshot(Snapshot),
+    /// Sweeps change the containers of the step before them
+    Sweep(Sweep),
+}
+
+/// The default time each frame is shown for, in ms
+fn default_dwell() -> f64 {
+    500.0
+}
+
+/// An ordered list of steps, which are played one frame after another
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Sequence {
+    pub steps: Vec<Step>,
+    /// How long each frame is shown for in ms, unless its step gives its own time
+    #[serde(default = "default_dwell")]
+    pub dwell: f64,
+    /// Whether the sequence starts again after the last frame
+    #[serde(default, rename = "loop")]
+    pub looped: bool,
+}
+
+impl Default for Sequence {
+    fn default() -> Sequence {
+        Sequence {
+            steps: Vec::new(),
+            dwell: default_dwell(),
+            looped: false,
+        }
+    }
+}
+
+/// One frame of a sequence
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub containers: HashMap<usize, PatternContainerData>,
+    /// How long the frame is shown for in ms, if it isn't the sequence's dwell time
+    pub dwell: Option<f64>,
+    /// The fields set by a sweep for this frame, labelled as in ```Axis::label```
+    pub parameters: Vec<(String, f64)>,
+}
+
+impl Frame {
+    /// How long the frame is shown for in ms, in a sequence with the given dwell time
+    pub fn dwell(&self, sequence_dwell: f64) -> f64 {
+        self.dwell.unwrap_or(sequence_dwell)
+    }
+
+    /// The name of the file the ```index```th frame is exported to, which gives the values
+    /// of the swept fields, as in ```0003_0.l=-7_pos.0=50.png```
+    pub fn file_name(&self, index: usize, extension: &str) -> String {
+        let mut name = format!("{:04}", index);
+        for (label, value) in &self.parameters {
+            name.push_str(&format!("_{}={}", label, format_value(*value)));
+        }
+        format!("{}.{}", name, extension)
+    }
+}
+
+impl Sequence {
+    /// Read a sequence from json
+    pub fn from_reader<R: Read>(reader: R) -> serde_json::Result<Sequence> {
+        serde_json::de::from_reader(reader)
+    }
+
+    /// Write the sequence as json
+    pub fn to_writer<W: Write>(&self, writer: W) -> serde_json::Result<()> {
+        serde_json::ser::to_writer_pretty(writer, self)
+    }
+
+    /// Expand the steps into frames, unless there would be more than ```MAX_FRAMES```.
+    /// A sweep at the start of the sequence changes ```containers```, which are usually the
+    /// ones in the editor
+    pub fn frames(
+        &self,
+        containers: &HashMap<usize, PatternContainerData>,
+    ) -> Result<Vec<Frame>, String> {
+        let mut frames: Vec<Frame> = Vec::new();
+        for step in &self.steps {
+            match step {
+                Step::Snapshot(snapshot) => frames.push(Frame {
+                    containers: snapshot.containers.clone(),
+                    dwell: snapshot.dwell,
+                    parameters: Vec::new(),
+                }),
+                Step::Sweep(sweep) => {
+                    if frames.len() as f64 + sweep.count() > MAX_FRAMES as f64 {
+                        return Err(format!(
+                            "The sweep of {} would make more than {} frames",
+                            sweep.axis.label(),
+                            MAX_FRAMES
+                        ));
+                    }
+                    let base = frames
+                        .last()
+                        .map_or(containers, |frame| &frame.containers)
+                        .clone();
+                    for values in sweep.values() {
+                        let mut containers = base.clone();
+                        for (axis, value) in &values {
+                            axis.apply(&mut containers, *value)?;
+                        }
+                        frames.push(Frame {
+                            containers,
+                            dwell: sweep.dwell,
+                            parameters: values
+                                .iter()
+                                .map(|(axis, value)| (axis.label(), *value))
+                                .collect(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(frames)
+    }
+}
+
+/// Render a frame for the device in ```data```, as 8 bit grey levels stored row by row.
+/// The frame's containers replace those in ```data```, and the correction is kept
+pub fn render_frame(frame: &Frame, data: &SLMData, calibration: &Calibration) -> Vec<u8> {
+    let data = SLMData {
+        device: data.device.clone(),
+        containers: frame.containers.clone(),
+        correction: data.correction.clone(),
+    };
+    render::render_device(&data, calibration)
+}
+
+/// Render each frame for the device in ```data```, and write it to the directory, named by
+/// ```Frame::file_name```. Without a format the device's grey levels are written as 8 bit pngs,
+/// as they are displayed; otherwise the wrapped phase is exported in the format.
+/// Returns the paths of the files
+pub fn export_frames<P: AsRef<Path>>(
+    directory: P,
+    frames: &[Frame],
+    data: &SLMData,
+    calibration: &Calibration,
+    format: Option<Format>,
+) -> io::Result<Vec<PathBuf>> {
+    let directory = directory.as_ref();
+    fs::create_dir_all(directory)?;
+    let extension = format.map_or("png", Format::extension);
+    frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let path = directory.join(frame.file_name(i, extension));
+            match format {
+                Some(format) => {
+                    let data = SLMData {
+                        device: data.device.clone(),
+                        containers: frame.containers.clone(),
+                        correction: data.correction.clone(),
+                    };
+                    export::export(&path, format, &data, calibration, true)?;
+                }
+                None => image::save_buffer(
+                    &path,
+                    &render_frame(frame, data, calibration),
+                    data.device.width as u32,
+                    data.device.height as u32,
+                    image::ColorType::L8,
+                )
+                .map_err(|e| io::Error::other(e.to_string()))?,
+            }
+            Ok(path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: f64, stop: f64, step: f64) -> Axis {
+        Axis {
+            container: 0,
+            pattern: Some(0),
+            name: String::from("l"),
+            start,
+            stop,
+            step,
+            values: None,
+        }
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let axis = Axis::parse(2, "0.l = -10:10:0.5").unwrap();
+        assert_eq!(
+            axis,
+            Axis {
+                container: 2,
+                ..range(-10.0, 10.0, 0.5)
+            }
+        );
+    }
+
+    #[test]
+    fn parses_lists_of_container_fields() {
+        let axis = Axis::parse(1, " pos.0 = 0, 50 ,200 ").unwrap();
+        assert_eq!(axis.container, 1);
+        assert_eq!(axis.pattern, None);
+        assert_eq!(axis.name, "pos.0");
+        assert_eq!(axis.values, Some(vec![0.0, 50.0, 200.0]));
+        assert_eq!(axis.label(), "pos.0");
+    }
+
+    #[test]
+    fn rejects_bad_axes() {
+        // no values
+        assert!(Axis::parse(0, "0.l").is_err());
+        // not a number
+        assert!(Axis::parse(0, "0.l = 0:ten:1").is_err());
+        assert!(Axis::parse(0, "0.l = 1, x").is_err());
+        // a pattern's parameter without its pattern
+        assert!(Axis::parse(0, "l = 0:10:1").is_err());
+    }
+
+    #[test]
+    fn values_include_the_stop() {
+        let values = range(0.0, 1.0, 0.1).values();
+        assert_eq!(values.len(), 11);
+        assert_eq!(values[3], 0.3);
+        assert_eq!(values[10], 1.0);
+        assert_eq!(range(-10.0, 10.0, 1.0).values().len(), 21);
+    }
+
+    #[test]
+    fn zero_step_gives_the_start() {
+        assert_eq!(range(5.0, 10.0, 0.0).values(), vec![5.0]);
+        assert_eq!(range(5.0, 5.0, 0.0).values(), vec![5.0]);
+    }
+
+    #[test]
+    fn negative_steps_count_down() {
+        assert_eq!(
+            range(1.0, 0.0, -0.25).values(),
+            vec![1.0, 0.75, 0.5, 0.25, 0.0]
+        );
+        // a step away from the stop
+        assert_eq!(range(0.0, 10.0, -1.0).values(), vec![0.0]);
+    }
+
+    #[test]
+    fn limits_the_number_of_values() {
+        assert!(Axis::parse(0, "0.l = 0:1000:1e-9").is_err());
+        assert!(Axis::parse(0, "0.l = 0:99999:1").is_ok());
+        let huge = range(0.0, 1000.0, 1e-9);
+        assert!(huge.count() > 1e11);
+        assert_eq!(huge.values().len(), MAX_FRAMES);
+        // sweeps loaded from json aren't parsed, so they are checked when they are expanded
+        let sequence = Sequence {
+            steps: vec![Step::Sweep(Sweep {
+                axis: range(0.0, 1000.0, 1.0),
+                inner: Some(range(0.0, 1000.0, 1.0)),
+                dwell: None,
+            })],
+            ..Sequence::default()
+        };
+        assert!(sequence.frames(&HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn file_names_give_rounded_values() {
+        let frame = Frame {
+            containers: HashMap::new(),
+            dwell: None,
+            parameters: vec![
+                (String::from("0.l"), 0.1 + 0.2),
+                (String::from("pos.0"), -7.0),
+                (String::from("scale.0"), -0.0000001),
+            ],
+        };
+        assert_eq!(
+            frame.file_name(3, "png"),
+            "0003_0.l=0.3_pos.0=-7_scale.0=0.png"
+        );
+    }
+}
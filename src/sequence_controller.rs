@@ -0,0 +1,775 @@
+//! This file contains the controls for sequences of holograms played on the SLM, and the
+//! syncing of the frames with other equipment once they are presented
+
+use self::SequenceControllerMsg::*;
+use gtk::{
+    BoxExt, ButtonExt, Cast, ComboBoxExtManual, ComboBoxTextExt, DialogExt, EditableSignals,
+    EntryExt, FileChooserExt, GridExt, LabelExt, SpinButtonExt, SpinButtonSignals, ToggleButtonExt,
+    WidgetExt,
+};
+use relm::{Relm, Update, Widget};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::gui::{SLMController, SLMControllerMsg};
+use phase::device::Calibration;
+use phase::export::Format;
+use phase::frame_sync::{
+    self, Delay, FrameSync, PresentedFrame, RemoteMessage, SyncThread, Toggle,
+};
+use phase::remote::Events;
+use phase::sequence::{self, Axis, Frame, Sequence, Snapshot, Step, Sweep};
+use phase::slm_data::SLMData;
+
+/// The number of frames of a sequence rendered ahead of the one being shown
+const SEQUENCE_LOOK_AHEAD: usize = 16;
+
+/// What the sequence does with the patterns in the editor, once the editor has sent them
+pub enum EditorAction {
+    /// Add the containers in the editor to the end of the sequence
+    AddFrame,
+    /// Add a sweep of the fields in the sweep entries of the current container
+    AddSweep,
+    /// Expand the sequence into frames
+    Prepare,
+}
+
+/// The patterns in the editor, which frames are added from and rendered with
+pub struct Editor {
+    pub data: SLMData,
+    /// The container shown in the editor, which sweeps are added for
+    pub current_container: Option<usize>,
+    pub calibration: Arc<Calibration>,
+}
+
+pub struct SequenceControllerModel {
+    parent_relm: Relm<SLMController>,
+    /// The sequence of holograms played on the SLM
+    sequence: Sequence,
+    /// The frames of the sequence, expanded when it was last prepared
+    frames: Vec<Frame>,
+    /// The device, correction and containers the frames are rendered with, from when the
+    /// sequence was last prepared
+    data: Arc<SLMData>,
+    calibration: Arc<Calibration>,
+    /// The grey levels of the rendered frames from the next one to be shown, by index.
+    /// Only the next ```SEQUENCE_LOOK_AHEAD``` frames are kept
+    cache: BTreeMap<usize, Vec<u8>>,
+    /// The frames being rendered on another thread
+    rendering: BTreeSet<usize>,
+    /// Whether the frame to show next wasn't rendered yet, so it is shown once it is
+    stalled: bool,
+    /// The frame which is shown next
+    index: usize,
+    playing: bool,
+    /// Counts the times the sequence is prepared, so frames rendered earlier are ignored
+    render: usize,
+    /// Counts the times playback starts or stops, so the timeouts of earlier playback are ignored
+    timer: usize,
+    /// The frame of the sequence sent to the output window, until it has been drawn
+    presenting: Option<usize>,
+    /// How long to wait for the SLM to settle after a frame is presented, in ms
+    sync_settle: f64,
+    /// Whether a frame event is sent to the remote control connections which subscribed
+    sync_notify: bool,
+    /// The GPIO value file or serial port pulsed after each frame, if there is one
+    sync_line: String,
+    /// How long the line is pulsed high for, in ms
+    sync_pulse: f64,
+    /// The remote control connections which subscribed to events
+    events: Events,
+}
+
+#[derive(Msg)]
+pub enum SequenceControllerMsg {
+    Load,
+    Save,
+    AddFrame,
+    Clear,
+    UpdateDwell(f64),
+    SetLoop(bool),
+    Play(bool),
+    Step,
+    AddSweep,
+    Export,
+    // the patterns in the editor, sent by the parent for the action which asked for them
+    Editor(EditorAction, Box<Editor>),
+    FrameRendered(usize, usize, Vec<u8>),
+    Tick(usize),
+    FramePresented,
+    UpdateSyncSettle(f64),
+    SetSyncNotify(bool),
+    UpdateSyncLine(String),
+    UpdateSyncPulse(f64),
+    FramesExported(Result<usize, String>),
+    SyncFailed(String),
+}
+
+pub struct SequenceController {
+    pub model: SequenceControllerModel,
+    pub widget: gtk::Grid,
+    relm: Relm<Self>,
+    /// how long each frame of the sequence is shown for, in ms
+    dwell: gtk::SpinButton,
+    /// whether the sequence starts again after the last frame
+    looped: gtk::CheckButton,
+    play: gtk::ToggleButton,
+    /// shows which frame of the sequence is displayed, or how many have been rendered
+    status: gtk::Label,
+    /// the field swept by a new sweep, such as ```0.l = -10:10:1```
+    sweep_axis: gtk::Entry,
+    /// the field swept for each value of the first, if there is one
+    sweep_inner: gtk::Entry,
+    /// receives the frames of the sequence, while they are being rendered
+    channel: Option<relm::Channel<SequenceControllerMsg>>,
+    /// receives the result of exporting the frames, while they are being exported
+    export_channel: Option<relm::Channel<SequenceControllerMsg>>,
+    /// syncs the frames of the sequence once they are presented, started with the first frame
+    frame_sync: Option<SyncThread>,
+    /// receives the failures of the syncs, while they are running
+    sync_channel: Option<relm::Channel<SequenceControllerMsg>>,
+}
+
+impl SequenceController {
+    /// The window the controls are in, which dialogs are shown over
+    fn window(&self) -> Option<gtk::Window> {
+        self.widget
+            .get_toplevel()
+            .and_then(|widget| widget.downcast::<gtk::Window>().ok())
+    }
+
+    /// Ask the editor for its patterns, which are sent back for the action
+    fn request_editor(&self, action: EditorAction) {
+        self.model
+            .parent_relm
+            .stream()
+            .emit(SLMControllerMsg::SequenceEditor(action));
+    }
+
+    /// Load a sequence saved as json
+    fn load(&mut self) {
+        use gtk::ResponseType;
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Load sequence"),
+            self.window().as_ref(),
+            gtk::FileChooserAction::Open,
+            &[
+                ("_Cancel", ResponseType::Cancel),
+                ("_Open", ResponseType::Accept),
+            ],
+        );
+        let filename = if ResponseType::from(dialog.run()) == ResponseType::Accept {
+            dialog.get_filename()
+        } else {
+            None
+        };
+        dialog.emit_close();
+        if let Some(filename) = filename {
+            let sequence = File::open(&filename)
+                .map_err(|e| e.to_string())
+                .and_then(|file| Sequence::from_reader(file).map_err(|e| e.to_string()));
+            match sequence {
+                Ok(sequence) => {
+                    self.dwell.set_value(sequence.dwell);
+                    self.looped.set_active(sequence.looped);
+                    self.model.sequence = sequence;
+                    self.request_editor(EditorAction::Prepare);
+                }
+                Err(e) => self
+                    .status
+                    .set_text(format!("Couldn't load {}: {}", filename.display(), e).as_str()),
+            }
+        }
+    }
+
+    fn save(&self) {
+        use gtk::ResponseType;
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Save sequence"),
+            self.window().as_ref(),
+            gtk::FileChooserAction::Save,
+            &[
+                ("_Cancel", ResponseType::Cancel),
+                ("_Save", ResponseType::Accept),
+            ],
+        );
+        dialog.set_do_overwrite_confirmation(true);
+        dialog.set_current_name("sequence.json");
+        if ResponseType::from(dialog.run()) == ResponseType::Accept {
+            if let Some(filename) = dialog.get_filename() {
+                if let Ok(file) = File::create(filename) {
+                    let _ = self.model.sequence.to_writer(file);
+                }
+            }
+        }
+        dialog.emit_close();
+    }
+
+    /// Carry out an action with the patterns the editor sent
+    fn with_editor(&mut self, action: EditorAction, editor: Editor) {
+        match action {
+            EditorAction::AddFrame => {
+                self.model.sequence.steps.push(Step::Snapshot(Snapshot {
+                    containers: editor.data.containers.clone(),
+                    dwell: None,
+                }));
+                self.prepare(editor);
+            }
+            EditorAction::AddSweep => self.add_sweep(editor),
+            EditorAction::Prepare => self.prepare(editor),
+        }
+    }
+
+    /// Expand the sequence into frames from the containers in the editor, and start rendering
+    /// the first of them on another thread so switching between them is fast
+    fn prepare(&mut self, editor: Editor) {
+        self.stop();
+        self.model.render += 1;
+        self.model.index = 0;
+        self.model.cache.clear();
+        self.model.rendering.clear();
+        self.model.stalled = false;
+        // replacing the channel of an earlier render stops its thread
+        self.channel = None;
+        self.model.frames = match self.model.sequence.frames(&editor.data.containers) {
+            Ok(frames) => frames,
+            Err(e) => {
+                self.model.frames.clear();
+                self.status.set_text(e.as_str());
+                return;
+            }
+        };
+        if self.model.frames.is_empty() {
+            self.status.set_text("No frames");
+            return;
+        }
+        self.model.data = Arc::new(editor.data);
+        self.model.calibration = editor.calibration;
+        self.status
+            .set_text(format!("Rendering {} frames", self.model.frames.len()).as_str());
+        self.render_ahead();
+    }
+
+    /// The indices of the frames which are shown next, starting at the next frame and
+    /// wrapping around to the start if the sequence loops
+    fn look_ahead(&self) -> Vec<usize> {
+        let count = self.model.frames.len();
+        let start = if self.model.index < count {
+            self.model.index
+        } else {
+            0
+        };
+        let ahead = SEQUENCE_LOOK_AHEAD.min(count);
+        if self.model.sequence.looped {
+            (start..start + ahead).map(|i| i % count).collect()
+        } else {
+            (start..count.min(start + ahead)).collect()
+        }
+    }
+
+    /// Forget the rendered frames which have been shown, and render the frames which are shown
+    /// next on another thread, unless some are still being rendered
+    fn render_ahead(&mut self) {
+        let ahead = self.look_ahead();
+        self.model.cache.retain(|index, _| ahead.contains(index));
+        if !self.model.rendering.is_empty() {
+            return;
+        }
+        let missing = ahead
+            .into_iter()
+            .filter(|index| !self.model.cache.contains_key(index))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            self.channel = None;
+            return;
+        }
+        self.model.rendering = missing.iter().cloned().collect();
+        let frames = missing
+            .iter()
+            .map(|&index| (index, self.model.frames[index].clone()))
+            .collect::<Vec<_>>();
+        let data = self.model.data.clone();
+        let calibration = self.model.calibration.clone();
+        let render = self.model.render;
+        let stream = self.relm.stream().clone();
+        let (channel, sender) = relm::Channel::new(move |msg| stream.emit(msg));
+        self.channel = Some(channel);
+        std::thread::spawn(move || {
+            for (i, frame) in frames {
+                let grey = sequence::render_frame(&frame, &data, &calibration);
+                if sender.send(FrameRendered(render, i, grey)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Add a sweep of the fields in the sweep entries of the current container to the sequence
+    fn add_sweep(&mut self, editor: Editor) {
+        let container = match editor.current_container {
+            Some(container) => container,
+            None => {
+                self.status.set_text("Add a container to sweep");
+                return;
+            }
+        };
+        let text = |entry: &gtk::Entry| {
+            entry
+                .get_text()
+                .map(|text| text.to_string())
+                .unwrap_or_default()
+        };
+        let inner = text(&self.sweep_inner);
+        let sweep = Axis::parse(container, &text(&self.sweep_axis)).and_then(|axis| {
+            let inner = if inner.trim().is_empty() {
+                None
+            } else {
+                Some(Axis::parse(container, &inner)?)
+            };
+            Ok(Sweep {
+                axis,
+                inner,
+                dwell: None,
+            })
+        });
+        match sweep {
+            Ok(sweep) => {
+                self.model.sequence.steps.push(Step::Sweep(sweep));
+                self.prepare(editor);
+            }
+            Err(e) => self.status.set_text(e.as_str()),
+        }
+    }
+
+    /// Export the frames of the sequence into a folder on another thread, as the grey levels
+    /// displayed or as the phase in a format chosen in the dialog
+    fn export(&mut self) {
+        use gtk::ResponseType;
+        if self.export_channel.is_some() {
+            return;
+        }
+        if self.model.frames.is_empty() {
+            self.status.set_text("No frames");
+            return;
+        }
+        let dialog = gtk::FileChooserDialog::with_buttons(
+            Some("Export frames"),
+            self.window().as_ref(),
+            gtk::FileChooserAction::SelectFolder,
+            &[
+                ("_Cancel", ResponseType::Cancel),
+                ("_Export", ResponseType::Accept),
+            ],
+        );
+        let options = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        let format_combo = gtk::ComboBoxText::new();
+        format_combo.append_text("grey levels");
+        for name in Format::NAMES {
+            format_combo.append_text(name);
+        }
+        format_combo.set_active(0);
+        options.pack_start(&gtk::Label::new("format"), false, false, 0);
+        options.pack_start(&format_combo, false, false, 0);
+        options.show_all();
+        dialog.set_extra_widget(&options);
+        let choice = if ResponseType::from(dialog.run()) == ResponseType::Accept {
+            let format = format_combo
+                .get_active_text()
+                .and_then(|name| Format::from_name(name.as_str()));
+            dialog.get_filename().map(|folder| (folder, format))
+        } else {
+            None
+        };
+        dialog.emit_close();
+        let (folder, format) = match choice {
+            Some(choice) => choice,
+            None => return,
+        };
+        let frames = self.model.frames.clone();
+        let data = self.model.data.clone();
+        let calibration = self.model.calibration.clone();
+        let stream = self.relm.stream().clone();
+        let (channel, sender) = relm::Channel::new(move |msg| stream.emit(msg));
+        self.export_channel = Some(channel);
+        self.status
+            .set_text(format!("Exporting {} frames", frames.len()).as_str());
+        std::thread::spawn(move || {
+            let exported = sequence::export_frames(&folder, &frames, &data, &calibration, format)
+                .map(|paths| paths.len())
+                .map_err(|e| format!("Couldn't export to {}: {}", folder.display(), e));
+            let _ = sender.send(FramesExported(exported));
+        });
+    }
+
+    /// Store a rendered frame, and show it if it was needed before it was rendered
+    fn frame_rendered(&mut self, render: usize, index: usize, grey: Vec<u8>) {
+        if render != self.model.render || !self.model.rendering.remove(&index) {
+            return;
+        }
+        self.model.cache.insert(index, grey);
+        if self.model.stalled && index == self.model.index {
+            self.model.stalled = false;
+            self.show_frame();
+        } else if !self.model.playing && self.model.rendering.is_empty() {
+            self.status
+                .set_text(format!("{} frames ready", self.model.frames.len()).as_str());
+        }
+        if self.model.rendering.is_empty() {
+            self.render_ahead();
+        }
+    }
+
+    /// Stop playing the sequence, leaving the current frame on the SLM
+    fn stop(&mut self) {
+        if self.model.playing {
+            self.model.playing = false;
+            self.model.timer += 1;
+            self.play.set_active(false);
+            self.model
+                .parent_relm
+                .stream()
+                .emit(SLMControllerMsg::SequencePlaying(false));
+        }
+    }
+
+    fn set_playing(&mut self, play: bool) {
+        if !play {
+            self.stop();
+            return;
+        }
+        if self.model.playing {
+            return;
+        }
+        if self.model.frames.is_empty() {
+            self.status.set_text("No frames");
+            self.play.set_active(false);
+            return;
+        }
+        self.model.playing = true;
+        self.model.timer += 1;
+        self.model
+            .parent_relm
+            .stream()
+            .emit(SLMControllerMsg::SequencePlaying(true));
+        self.show_frame();
+    }
+
+    /// Show the next frame on the SLM and in the preview. While the sequence is playing,
+    /// the frame after it is shown when its dwell time is up
+    fn show_frame(&mut self) {
+        let count = self.model.frames.len();
+        if count == 0 {
+            return;
+        }
+        if self.model.index >= count {
+            self.model.index = 0;
+        }
+        let index = self.model.index;
+        let grey = match self.model.cache.get(&index) {
+            Some(grey) => grey.clone(),
+            None => {
+                self.status
+                    .set_text(format!("Rendering frame {}", index + 1).as_str());
+                self.model.stalled = true;
+                self.render_ahead();
+                return;
+            }
+        };
+        if self.model.playing {
+            // the timeout is started first, so the time taken to show the frame doesn't add to it
+            let dwell = self.model.frames[index].dwell(self.model.sequence.dwell);
+            let timer = self.model.timer;
+            relm::timeout(
+                self.relm.stream(),
+                dwell.max(1.0).round() as u32,
+                move || Tick(timer),
+            );
+        }
+        self.model
+            .parent_relm
+            .stream()
+            .emit(SLMControllerMsg::ShowSequenceFrame(grey));
+        self.model.presenting = Some(index);
+        self.status
+            .set_text(format!("Frame {} of {}", index + 1, count).as_str());
+        self.model.index = index + 1;
+        self.render_ahead();
+    }
+
+    /// The syncs called after each frame of the sequence is presented, from the sync settings.
+    /// Their failures are shown in the status
+    fn start_frame_sync(&mut self) -> SyncThread {
+        let mut syncs: Vec<Box<dyn FrameSync>> = Vec::new();
+        if self.model.sync_settle > 0.0 {
+            syncs.push(Box::new(Delay {
+                settle: Duration::from_secs_f64(self.model.sync_settle / 1000.0),
+            }));
+        }
+        if self.model.sync_notify {
+            syncs.push(Box::new(RemoteMessage {
+                events: self.model.events.clone(),
+            }));
+        }
+        let line = self.model.sync_line.trim();
+        if !line.is_empty() {
+            match frame_sync::open_line(line) {
+                Ok(line) => syncs.push(Box::new(Toggle {
+                    line,
+                    pulse: Duration::from_secs_f64(self.model.sync_pulse / 1000.0),
+                })),
+                Err(e) => self
+                    .status
+                    .set_text(format!("Couldn't open {}: {}", line, e).as_str()),
+            }
+        }
+        let stream = self.relm.stream().clone();
+        let (channel, sender) = relm::Channel::new(move |msg| stream.emit(msg));
+        self.sync_channel = Some(channel);
+        SyncThread::spawn(syncs, move |frame, e| {
+            let _ = sender.send(SyncFailed(format!(
+                "Couldn't sync frame {}: {}",
+                frame.index + 1,
+                e
+            )));
+        })
+    }
+
+    /// Sync the frame of the sequence which has just been drawn in the output window
+    fn frame_presented(&mut self) {
+        let index = match self.model.presenting.take() {
+            Some(index) => index,
+            None => return,
+        };
+        let parameters = match self.model.frames.get(index) {
+            Some(frame) => frame.parameters.clone(),
+            None => return,
+        };
+        if self.frame_sync.is_none() {
+            self.frame_sync = Some(self.start_frame_sync());
+        }
+        if let Some(frame_sync) = &self.frame_sync {
+            frame_sync.frame_presented(PresentedFrame { index, parameters });
+        }
+    }
+
+    /// Move on to the next frame when the dwell time of the current one is up
+    fn tick(&mut self, timer: usize) {
+        if timer != self.model.timer || !self.model.playing {
+            return;
+        }
+        let finished = self.model.index >= self.model.frames.len();
+        if finished && !self.model.sequence.looped {
+            self.stop();
+            self.model.index = 0;
+            self.render_ahead();
+            self.status.set_text("Finished");
+            return;
+        }
+        self.show_frame();
+    }
+}
+
+impl Update for SequenceController {
+    type Model = SequenceControllerModel;
+    /// The remote control connections which frame events are sent to, and the parent
+    type ModelParam = (Events, Relm<SLMController>);
+    type Msg = SequenceControllerMsg;
+
+    fn model(_: &Relm<Self>, (events, parent_relm): Self::ModelParam) -> Self::Model {
+        SequenceControllerModel {
+            parent_relm,
+            sequence: Sequence::default(),
+            frames: Vec::new(),
+            data: Arc::new(SLMData::default()),
+            calibration: Arc::new(Calibration::default()),
+            cache: BTreeMap::new(),
+            rendering: BTreeSet::new(),
+            stalled: false,
+            index: 0,
+            playing: false,
+            render: 0,
+            timer: 0,
+            presenting: None,
+            sync_settle: 0.0,
+            sync_notify: false,
+            sync_line: String::new(),
+            sync_pulse: 10.0,
+            events,
+        }
+    }
+
+    fn update(&mut self, event: Self::Msg) {
+        match event {
+            Load => self.load(),
+            Save => self.save(),
+            AddFrame => self.request_editor(EditorAction::AddFrame),
+            Clear => {
+                self.model.sequence.steps.clear();
+                self.request_editor(EditorAction::Prepare);
+            }
+            UpdateDwell(dwell) => self.model.sequence.dwell = dwell,
+            SetLoop(looped) => self.model.sequence.looped = looped,
+            Play(play) => self.set_playing(play),
+            Step => {
+                self.stop();
+                self.show_frame();
+            }
+            AddSweep => self.request_editor(EditorAction::AddSweep),
+            Export => self.export(),
+            Editor(action, editor) => self.with_editor(action, *editor),
+            FrameRendered(render, index, grey) => self.frame_rendered(render, index, grey),
+            Tick(timer) => self.tick(timer),
+            FramePresented => self.frame_presented(),
+            UpdateSyncSettle(settle) => {
+                self.model.sync_settle = settle;
+                self.frame_sync = None;
+            }
+            SetSyncNotify(notify) => {
+                self.model.sync_notify = notify;
+                self.frame_sync = None;
+            }
+            UpdateSyncLine(line) => {
+                self.model.sync_line = line;
+                self.frame_sync = None;
+            }
+            UpdateSyncPulse(pulse) => {
+                self.model.sync_pulse = pulse;
+                self.frame_sync = None;
+            }
+            SyncFailed(e) => self.status.set_text(e.as_str()),
+            FramesExported(exported) => {
+                self.export_channel = None;
+                match exported {
+                    Ok(count) => self
+                        .status
+                        .set_text(format!("Exported {} frames", count).as_str()),
+                    Err(e) => self.status.set_text(e.as_str()),
+                }
+            }
+        }
+    }
+}
+
+impl Widget for SequenceController {
+    type Root = gtk::Grid;
+
+    fn root(&self) -> Self::Root {
+        self.widget.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+        let grid_widget = gtk::Grid::new();
+        grid_widget.set_column_spacing(5);
+        let load_button = gtk::Button::new_with_label("Load sequence");
+        let save_button = gtk::Button::new_with_label("Save sequence");
+        let add_button = gtk::Button::new_with_label("Add frame");
+        let clear_button = gtk::Button::new_with_label("Clear");
+        let dwell_adjustment =
+            gtk::Adjustment::new(model.sequence.dwell, 1.0, 3600000.0, 10.0, 100.0, 0.0);
+        let dwell = gtk::SpinButton::new(&dwell_adjustment, 0.0, 0);
+        let looped = gtk::CheckButton::new_with_label("loop");
+        let play = gtk::ToggleButton::new_with_label("Play");
+        let step_button = gtk::Button::new_with_label("Step");
+        let status = gtk::Label::new("No frames");
+        grid_widget.attach(&load_button, 0, 0, 1, 1);
+        grid_widget.attach(&save_button, 1, 0, 1, 1);
+        grid_widget.attach(&add_button, 2, 0, 1, 1);
+        grid_widget.attach(&clear_button, 3, 0, 1, 1);
+        grid_widget.attach(&gtk::Label::new("dwell (ms)"), 0, 1, 1, 1);
+        grid_widget.attach(&dwell, 1, 1, 1, 1);
+        grid_widget.attach(&looped, 2, 1, 1, 1);
+        grid_widget.attach(&play, 0, 2, 1, 1);
+        grid_widget.attach(&step_button, 1, 2, 1, 1);
+        grid_widget.attach(&status, 2, 2, 2, 1);
+        let sweep_axis = gtk::Entry::new();
+        sweep_axis.set_placeholder_text("0.l = -10:10:1");
+        let sweep_inner = gtk::Entry::new();
+        sweep_inner.set_placeholder_text("pos.0 = 0, 50, 200");
+        let sweep_button = gtk::Button::new_with_label("Add sweep");
+        let export_button = gtk::Button::new_with_label("Export frames");
+        grid_widget.attach(&gtk::Label::new("sweep"), 0, 3, 1, 1);
+        grid_widget.attach(&sweep_axis, 1, 3, 3, 1);
+        grid_widget.attach(&gtk::Label::new("nested sweep"), 0, 4, 1, 1);
+        grid_widget.attach(&sweep_inner, 1, 4, 3, 1);
+        grid_widget.attach(&sweep_button, 0, 5, 1, 1);
+        grid_widget.attach(&export_button, 1, 5, 1, 1);
+        let settle_adjustment =
+            gtk::Adjustment::new(model.sync_settle, 0.0, 60000.0, 1.0, 10.0, 0.0);
+        let settle_spin = gtk::SpinButton::new(&settle_adjustment, 0.0, 0);
+        let notify_button = gtk::CheckButton::new_with_label("send frame events");
+        let sync_line = gtk::Entry::new();
+        sync_line.set_placeholder_text("/dev/ttyUSB0 or /sys/class/gpio/gpio17/value");
+        let pulse_adjustment = gtk::Adjustment::new(model.sync_pulse, 0.0, 60000.0, 1.0, 10.0, 0.0);
+        let pulse_spin = gtk::SpinButton::new(&pulse_adjustment, 0.0, 0);
+        grid_widget.attach(&gtk::Label::new("settle (ms)"), 0, 6, 1, 1);
+        grid_widget.attach(&settle_spin, 1, 6, 1, 1);
+        grid_widget.attach(&notify_button, 2, 6, 2, 1);
+        grid_widget.attach(&gtk::Label::new("trigger line"), 0, 7, 1, 1);
+        grid_widget.attach(&sync_line, 1, 7, 1, 1);
+        grid_widget.attach(&gtk::Label::new("pulse (ms)"), 2, 7, 1, 1);
+        grid_widget.attach(&pulse_spin, 3, 7, 1, 1);
+
+        connect!(relm, load_button, connect_clicked(_), Load);
+        connect!(relm, save_button, connect_clicked(_), Save);
+        connect!(relm, add_button, connect_clicked(_), AddFrame);
+        connect!(relm, clear_button, connect_clicked(_), Clear);
+        connect!(
+            relm,
+            dwell,
+            connect_value_changed(x),
+            UpdateDwell(x.get_value())
+        );
+        connect!(relm, looped, connect_toggled(x), SetLoop(x.get_active()));
+        connect!(relm, play, connect_toggled(x), Play(x.get_active()));
+        connect!(relm, step_button, connect_clicked(_), Step);
+        connect!(relm, sweep_button, connect_clicked(_), AddSweep);
+        connect!(relm, export_button, connect_clicked(_), Export);
+        connect!(
+            relm,
+            settle_spin,
+            connect_value_changed(x),
+            UpdateSyncSettle(x.get_value())
+        );
+        connect!(
+            relm,
+            notify_button,
+            connect_toggled(x),
+            SetSyncNotify(x.get_active())
+        );
+        connect!(
+            relm,
+            sync_line,
+            connect_changed(x),
+            UpdateSyncLine(
+                x.get_text()
+                    .map(|text| text.to_string())
+                    .unwrap_or_default()
+            )
+        );
+        connect!(
+            relm,
+            pulse_spin,
+            connect_value_changed(x),
+            UpdateSyncPulse(x.get_value())
+        );
+        grid_widget.show_all();
+
+        SequenceController {
+            model,
+            widget: grid_widget,
+            relm: relm.clone(),
+            dwell,
+            looped,
+            play,
+            status,
+            sweep_axis,
+            sweep_inner,
+            channel: None,
+            export_channel: None,
+            frame_sync: None,
+            sync_channel: None,
+        }
+    }
+}
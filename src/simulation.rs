@@ -0,0 +1,169 @@
+//! This module simulates what a hologram does on the optical bench, by finding the far field
+//! (the Fourier plane of a lens) of the light reflected from the SLM.
+//! The SLM is lit by the containers' illumination, and elsewhere by a uniform or Gaussian beam.
+//! The far field is shown with the zero order in the centre, in the same coordinates as the
+//! trap editor
+use num_complex::Complex64;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::fourier::{shift, Fft2};
+use crate::slm_data::PatternContainerData;
+
+/// A Gaussian beam lighting the SLM
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct GaussianBeam {
+    /// The radius where the intensity falls to 1/e², in pixels
+    pub waist: f64,
+    /// The centre of the beam on the SLM, in pixels
+    pub centre: (f64, f64),
+}
+
+impl GaussianBeam {
+    /// The amplitude of the beam at the pixel (x, y), which is 1 in the centre
+    pub fn amplitude(&self, x: f64, y: f64) -> f64 {
+        if self.waist <= 0.0 {
+            return 0.0;
+        }
+        let (dx, dy) = (x - self.centre.0, y - self.centre.1);
+        (-(dx * dx + dy * dy) / (self.waist * self.waist)).exp()
+    }
+}
+
+/// The amplitude lighting each pixel of a ```width``` x ```height``` SLM, stored row by row,
+/// from the beam, or a uniform beam if there is none
+pub fn beam_amplitude(width: usize, height: usize, beam: Option<&GaussianBeam>) -> Vec<f64> {
+    (0..width * height)
+        .map(|i| {
+            beam.map_or(1.0, |beam| {
+                beam.amplitude((i % width) as f64, (i / width) as f64)
+            })
+        })
+        .collect()
+}
+
+/// The amplitude lighting each pixel of a ```width``` x ```height``` SLM, stored row by row.
+/// Pixels in the crop rectangle of a container with illumination are lit by its beam
+/// (the first container by id, if they overlap), and the rest by the beam, or a uniform
+/// beam if there is none
+pub fn container_amplitude(
+    containers: &HashMap<usize, PatternContainerData>,
+    width: usize,
+    height: usize,
+    beam: Option<&GaussianBeam>,
+) -> Vec<f64> {
+    let mut ids = containers.keys().collect::<Vec<_>>();
+    ids.sort();
+    let lit = ids
+        .into_iter()
+        .map(|id| &containers[id])
+        .filter_map(|container| Some((container, container.illumination.as_ref()?)))
+        .collect::<Vec<_>>();
+    let mut amplitude = beam_amplitude(width, height, beam);
+    for (i, a) in amplitude.iter_mut().enumerate() {
+        let (x, y) = ((i % width) as f64, (i / width) as f64);
+        if let Some((container, illumination)) =
+            lit.iter().find(|(container, _)| container.contains(x, y))
+        {
+            *a = illumination.amplitude(x - container.pos.0, y - container.pos.1);
+        }
+    }
+    amplitude
+}
+
+/// The field just after the SLM for a phase lit with the amplitude
+pub fn illuminate(phase: &[f64], amplitude: &[f64]) -> Vec<Complex64> {
+    phase
+        .iter()
+        .zip(amplitude.iter())
+        .map(|(&p, &a)| Complex64::from_polar(&a, &p))
+        .collect()
+}
+
+/// The intensity in the far field of a ```width``` x ```height``` phase lit with the amplitude.
+/// The far field has the same size as the phase, with the zero order in the centre,
+/// and is normalised so its brightest pixel is 1
+pub fn far_field(phase: &[f64], amplitude: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let mut field = illuminate(phase, amplitude);
+    if field.len() != width * height || field.is_empty() {
+        return vec![0.0; width * height];
+    }
+    Fft2::new(width, height, false).process(&mut field);
+    let intensity = field.iter().map(|f| f.norm_sqr()).collect::<Vec<_>>();
+    normalise(shift(&intensity, width, height))
+}
+
+/// Scale the intensity so its brightest pixel is 1, for display
+pub fn normalise(mut intensity: Vec<f64>) -> Vec<f64> {
+    let peak = intensity.iter().cloned().fold(0.0, f64::max);
+    if peak > 0.0 {
+        for i in intensity.iter_mut() {
+            *i /= peak;
+        }
+    }
+    intensity
+}
+
+/// Convert an intensity normalised to 1 into 8 bit grey levels for display.
+/// A logarithmic scale over ```decades``` orders of magnitude shows faint features
+/// next to the bright zero order; otherwise the scale is linear
+pub fn intensity_to_grey(intensity: &[f64], logarithmic: bool, decades: f64) -> Vec<u8> {
+    intensity
+        .iter()
+        .map(|&i| {
+            let level = if logarithmic {
+                1.0 + i.max(1e-300).log10() / decades.max(f64::EPSILON)
+            } else {
+                i
+            };
+            (level.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 16;
+
+    /// The index of the brightest pixel
+    fn brightest(intensity: &[f64]) -> usize {
+        (0..intensity.len())
+            .max_by(|&a, &b| intensity[a].partial_cmp(&intensity[b]).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn a_grating_moves_the_far_field_peak() {
+        // 4 cycles across the SLM in x, and -3 in y
+        let k = (
+            2.0 * PI * 4.0 / WIDTH as f64,
+            -2.0 * PI * 3.0 / HEIGHT as f64,
+        );
+        let phase = (0..WIDTH * HEIGHT)
+            .map(|i| k.0 * (i % WIDTH) as f64 + k.1 * (i / WIDTH) as f64)
+            .collect::<Vec<_>>();
+        let amplitude = beam_amplitude(WIDTH, HEIGHT, None);
+        let intensity = far_field(&phase, &amplitude, WIDTH, HEIGHT);
+        let peak = brightest(&intensity);
+        assert_eq!(peak, (HEIGHT / 2 - 3) * WIDTH + WIDTH / 2 + 4);
+        assert_eq!(intensity[peak], 1.0);
+        let rest = intensity.iter().sum::<f64>() - intensity[peak];
+        assert!(rest < 1e-12);
+    }
+
+    #[test]
+    fn a_uniform_phase_stays_in_the_zero_order() {
+        let phase = vec![1.3; WIDTH * HEIGHT];
+        let amplitude = beam_amplitude(WIDTH, HEIGHT, None);
+        let intensity = far_field(&phase, &amplitude, WIDTH, HEIGHT);
+        let centre = HEIGHT / 2 * WIDTH + WIDTH / 2;
+        assert_eq!(brightest(&intensity), centre);
+        assert_eq!(intensity[centre], 1.0);
+        let rest = intensity.iter().sum::<f64>() - intensity[centre];
+        assert!(rest < 1e-12);
+    }
+}
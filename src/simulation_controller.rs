@@ -0,0 +1,419 @@
+//! This file contains the controls for the simulated light after the SLM, and the area beside
+//! the preview which the simulation is shown in
+
+use self::SimulationControllerMsg::*;
+use gdk::ContextExt;
+use gtk::{
+    GridExt, RangeExt, ScaleExt, SpinButtonExt, SpinButtonSignals, ToggleButtonExt, WidgetExt,
+};
+use relm::{DrawHandler, Relm, Update, Widget};
+use std::collections::HashMap;
+
+use crate::gui::grey_to_pixbuf;
+use phase::device::DeviceProfile;
+use phase::propagation::{Optics, Propagator};
+use phase::render;
+use phase::simulation::{self, GaussianBeam};
+use phase::slm_data::PatternContainerData;
+
+/// The number of orders of magnitude shown by the simulation's logarithmic scale
+const SIMULATION_DECADES: f64 = 4.0;
+
+/// A phase rendered for the device, with what lights it
+pub struct RenderedPhase {
+    /// The wrapped phase of each pixel, stored row by row
+    pub phase: Vec<f64>,
+    pub device: DeviceProfile,
+    /// The containers with illumination, which light their part of the device
+    pub containers: HashMap<usize, PatternContainerData>,
+    /// The wavelength of the current container's illumination, if it has one
+    pub wavelength: Option<f64>,
+}
+
+pub struct SimulationControllerModel {
+    /// The area the simulation is shown in, which the parent packs beside the preview
+    area: gtk::DrawingArea,
+    /// The last rendered phase, which is simulated
+    rendered: Option<RenderedPhase>,
+    /// Whether the simulated light is shown next to the preview
+    show: bool,
+    /// Whether the simulated SLM is lit by ```beam```, rather than a uniform beam
+    gaussian_illumination: bool,
+    beam: GaussianBeam,
+    /// Whether the intensity is shown on a logarithmic scale
+    log_scale: bool,
+    buffer: Option<gdk_pixbuf::Pixbuf>,
+    /// Whether the light is propagated to ```distance``` rather than to the far field
+    propagate: bool,
+    /// The focal length of the lens against the SLM in mm, where 0 is no lens
+    focal_length: f64,
+    /// The distance from the SLM the light is propagated to, in mm
+    distance: f64,
+    /// Whether the phase of the propagated light is shown instead of its intensity
+    show_propagated_phase: bool,
+    /// The spectrum of the last rendered phase, which is kept while only the distance changes.
+    /// It is taken by the simulation thread while the light is being simulated
+    propagator: Option<Propagator>,
+    /// Counts the changes to the phase, illumination and lens, so a spectrum found from ones
+    /// which have since changed isn't kept
+    propagator_generation: usize,
+    /// Whether the simulation changed while it was being simulated, so it is simulated again
+    /// once the simulation finishes
+    pending: bool,
+}
+
+#[derive(Msg)]
+pub enum SimulationControllerMsg {
+    SetPhase(RenderedPhase),
+    Show(bool),
+    SetGaussianIllumination(bool),
+    UpdateBeamWaist(f64),
+    UpdateBeamCentreX(f64),
+    UpdateBeamCentreY(f64),
+    SetLogScale(bool),
+    SetPropagation(bool),
+    UpdateFocalLength(f64),
+    UpdatePropagationDistance(f64),
+    SetShowPropagatedPhase(bool),
+    Paint,
+    // the simulated grey levels, and the spectrum they were propagated from with its generation
+    Simulated(Vec<u8>, Option<(Propagator, usize)>),
+}
+
+pub struct SimulationController {
+    pub model: SimulationControllerModel,
+    pub widget: gtk::Grid,
+    relm: Relm<Self>,
+    handler: DrawHandler<gtk::DrawingArea>,
+    /// receives the simulated light, while it is being simulated
+    channel: Option<relm::Channel<SimulationControllerMsg>>,
+}
+
+impl SimulationController {
+    /// The amplitude lighting each pixel of the device in the simulation.
+    /// Containers with illumination are lit by it, and the rest of the device by the
+    /// simulation's beam
+    fn illumination_amplitude(&self, rendered: &RenderedPhase) -> Vec<f64> {
+        let beam = if self.model.gaussian_illumination {
+            Some(&self.model.beam)
+        } else {
+            None
+        };
+        simulation::container_amplitude(
+            &rendered.containers,
+            rendered.device.width,
+            rendered.device.height,
+            beam,
+        )
+    }
+
+    /// The optics of the simulation, at the wavelength of the current container's illumination
+    /// if it has one, or otherwise the device's wavelength
+    fn optics(&self, rendered: &RenderedPhase) -> Optics {
+        let focal_length = Some(self.model.focal_length).filter(|&f| f > 0.0);
+        let mut optics = Optics::for_device(&rendered.device, focal_length);
+        if let Some(wavelength) = rendered.wavelength {
+            optics.wavelength = wavelength;
+        }
+        optics
+    }
+
+    /// Find the far field, or the light at the propagation distance, of the last rendered phase
+    /// on another thread, and paint it into the simulation area once it is found.
+    /// Changes made while the light is being simulated are simulated together once it finishes
+    fn simulate(&mut self) {
+        if !self.model.show {
+            return;
+        }
+        if self.channel.is_some() {
+            self.model.pending = true;
+            return;
+        }
+        let rendered = match self.model.rendered {
+            Some(ref rendered) => rendered,
+            None => return,
+        };
+        let (width, height) = (rendered.device.width, rendered.device.height);
+        let phase = rendered.phase.clone();
+        let amplitude = self.illumination_amplitude(rendered);
+        let optics = self.optics(rendered);
+        let propagate = self.model.propagate;
+        let propagator = self.model.propagator.take();
+        let generation = self.model.propagator_generation;
+        let distance = self.model.distance;
+        let show_phase = self.model.show_propagated_phase;
+        let log_scale = self.model.log_scale;
+        let stream = self.relm.stream().clone();
+        let (channel, sender) = relm::Channel::new(move |msg| stream.emit(msg));
+        self.channel = Some(channel);
+        std::thread::spawn(move || {
+            let message = if propagate {
+                let propagator = propagator
+                    .unwrap_or_else(|| Propagator::new(&phase, &amplitude, width, height, &optics));
+                let plane = propagator.plane(distance);
+                let grey = if show_phase {
+                    plane.phase.into_iter().map(render::phase_to_grey).collect()
+                } else {
+                    simulation::intensity_to_grey(
+                        &simulation::normalise(plane.intensity),
+                        log_scale,
+                        SIMULATION_DECADES,
+                    )
+                };
+                Simulated(grey, Some((propagator, generation)))
+            } else {
+                let intensity = simulation::far_field(&phase, &amplitude, width, height);
+                Simulated(
+                    simulation::intensity_to_grey(&intensity, log_scale, SIMULATION_DECADES),
+                    None,
+                )
+            };
+            let _ = sender.send(message);
+        });
+    }
+
+    /// Paint the simulated light, keep its spectrum if nothing it was found from has changed
+    /// since, and simulate again if the simulation changed while it was running
+    fn simulated(&mut self, grey: Vec<u8>, propagator: Option<(Propagator, usize)>) {
+        self.channel = None;
+        if let Some((propagator, generation)) = propagator {
+            if generation == self.model.propagator_generation {
+                self.model.propagator = Some(propagator);
+            }
+        }
+        if let Some(ref rendered) = self.model.rendered {
+            let (width, height) = (rendered.device.width, rendered.device.height);
+            if grey.len() == width * height {
+                self.model.buffer = Some(grey_to_pixbuf(&grey, width as i32, height as i32));
+                self.paint();
+            }
+        }
+        if self.model.pending {
+            self.model.pending = false;
+            self.simulate();
+        }
+    }
+
+    /// Paint the last simulation into the simulation area, scaled to fit
+    fn paint(&mut self) {
+        let allocation = self.model.area.get_allocation();
+        let context = self.handler.get_context();
+        context.set_source_rgb(0.0, 0.0, 0.0);
+        context.paint();
+        if let Some(ref buffer) = self.model.buffer {
+            let scale = (allocation.width as f64 / buffer.get_width() as f64)
+                .min(allocation.height as f64 / buffer.get_height() as f64);
+            context.scale(scale, scale);
+            context.set_source_pixbuf(buffer, 0.0, 0.0);
+            context.paint();
+        }
+    }
+
+    /// Resimulate the light after the phase, illumination or lens change, if it is shown
+    fn resimulate(&mut self) {
+        self.model.propagator = None;
+        self.model.propagator_generation += 1;
+        self.simulate();
+    }
+}
+
+impl Update for SimulationController {
+    type Model = SimulationControllerModel;
+    /// The area the simulation is shown in, and the device it starts with
+    type ModelParam = (gtk::DrawingArea, DeviceProfile);
+    type Msg = SimulationControllerMsg;
+
+    fn model(_: &Relm<Self>, (area, device): Self::ModelParam) -> Self::Model {
+        SimulationControllerModel {
+            area,
+            rendered: None,
+            show: false,
+            gaussian_illumination: false,
+            beam: GaussianBeam {
+                waist: device.width.min(device.height) as f64 / 4.0,
+                centre: (device.width as f64 / 2.0, device.height as f64 / 2.0),
+            },
+            log_scale: true,
+            buffer: None,
+            propagate: false,
+            focal_length: 200.0,
+            distance: 200.0,
+            show_propagated_phase: false,
+            propagator: None,
+            propagator_generation: 0,
+            pending: false,
+        }
+    }
+
+    fn update(&mut self, event: Self::Msg) {
+        match event {
+            SetPhase(rendered) => {
+                self.model.rendered = Some(rendered);
+                self.resimulate();
+            }
+            Show(show) => {
+                self.model.show = show;
+                self.model.area.set_visible(show);
+                self.simulate();
+            }
+            SetGaussianIllumination(gaussian) => {
+                self.model.gaussian_illumination = gaussian;
+                self.resimulate();
+            }
+            UpdateBeamWaist(x) => {
+                self.model.beam.waist = x;
+                self.resimulate();
+            }
+            UpdateBeamCentreX(x) => {
+                self.model.beam.centre.0 = x;
+                self.resimulate();
+            }
+            UpdateBeamCentreY(x) => {
+                self.model.beam.centre.1 = x;
+                self.resimulate();
+            }
+            SetLogScale(log_scale) => {
+                self.model.log_scale = log_scale;
+                self.simulate();
+            }
+            SetPropagation(propagate) => {
+                self.model.propagate = propagate;
+                self.resimulate();
+            }
+            UpdateFocalLength(x) => {
+                self.model.focal_length = x;
+                self.resimulate();
+            }
+            UpdatePropagationDistance(x) => {
+                self.model.distance = x;
+                self.simulate();
+            }
+            SetShowPropagatedPhase(show) => {
+                self.model.show_propagated_phase = show;
+                self.simulate();
+            }
+            Paint => self.paint(),
+            Simulated(grey, propagator) => self.simulated(grey, propagator),
+        }
+    }
+}
+
+impl Widget for SimulationController {
+    type Root = gtk::Grid;
+
+    fn root(&self) -> Self::Root {
+        self.widget.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+        let grid_widget = gtk::Grid::new();
+        grid_widget.set_column_spacing(5);
+        let show_button = gtk::CheckButton::new_with_label("show simulation");
+        let log_scale_button = gtk::CheckButton::new_with_label("log scale");
+        log_scale_button.set_active(model.log_scale);
+        let gaussian_button = gtk::CheckButton::new_with_label("gaussian illumination");
+        let waist_adjustment =
+            gtk::Adjustment::new(model.beam.waist, 0.0, 100000.0, 1.0, 10.0, 0.0);
+        let waist_spin = gtk::SpinButton::new(&waist_adjustment, 0.0, 1);
+        let beam_x_adjustment =
+            gtk::Adjustment::new(model.beam.centre.0, -100000.0, 100000.0, 1.0, 10.0, 0.0);
+        let beam_x_spin = gtk::SpinButton::new(&beam_x_adjustment, 0.0, 1);
+        let beam_y_adjustment =
+            gtk::Adjustment::new(model.beam.centre.1, -100000.0, 100000.0, 1.0, 10.0, 0.0);
+        let beam_y_spin = gtk::SpinButton::new(&beam_y_adjustment, 0.0, 1);
+        grid_widget.attach(&show_button, 0, 0, 2, 1);
+        grid_widget.attach(&log_scale_button, 2, 0, 2, 1);
+        grid_widget.attach(&gaussian_button, 0, 1, 2, 1);
+        grid_widget.attach(&gtk::Label::new("waist"), 2, 1, 1, 1);
+        grid_widget.attach(&waist_spin, 3, 1, 1, 1);
+        grid_widget.attach(&gtk::Label::new("centre x"), 0, 2, 1, 1);
+        grid_widget.attach(&beam_x_spin, 1, 2, 1, 1);
+        grid_widget.attach(&gtk::Label::new("centre y"), 2, 2, 1, 1);
+        grid_widget.attach(&beam_y_spin, 3, 2, 1, 1);
+        let propagate_button = gtk::CheckButton::new_with_label("propagate to z");
+        let propagated_phase_button = gtk::CheckButton::new_with_label("show phase");
+        let focal_length_adjustment =
+            gtk::Adjustment::new(model.focal_length, 0.0, 100000.0, 1.0, 10.0, 0.0);
+        let focal_length_spin = gtk::SpinButton::new(&focal_length_adjustment, 0.0, 1);
+        let distance_adjustment = gtk::Adjustment::new(model.distance, 0.0, 2000.0, 0.1, 10.0, 0.0);
+        let distance_scale =
+            gtk::Scale::new(gtk::Orientation::Horizontal, Some(&distance_adjustment));
+        distance_scale.set_digits(1);
+        distance_scale.set_hexpand(true);
+        grid_widget.attach(&propagate_button, 0, 3, 2, 1);
+        grid_widget.attach(&propagated_phase_button, 2, 3, 2, 1);
+        grid_widget.attach(&gtk::Label::new("lens focal length (mm)"), 0, 4, 2, 1);
+        grid_widget.attach(&focal_length_spin, 2, 4, 2, 1);
+        grid_widget.attach(&gtk::Label::new("z (mm)"), 0, 5, 1, 1);
+        grid_widget.attach(&distance_scale, 1, 5, 3, 1);
+
+        connect!(relm, show_button, connect_toggled(x), Show(x.get_active()));
+        connect!(
+            relm,
+            log_scale_button,
+            connect_toggled(x),
+            SetLogScale(x.get_active())
+        );
+        connect!(
+            relm,
+            gaussian_button,
+            connect_toggled(x),
+            SetGaussianIllumination(x.get_active())
+        );
+        connect!(
+            relm,
+            waist_spin,
+            connect_value_changed(x),
+            UpdateBeamWaist(x.get_value())
+        );
+        connect!(
+            relm,
+            beam_x_spin,
+            connect_value_changed(x),
+            UpdateBeamCentreX(x.get_value())
+        );
+        connect!(
+            relm,
+            beam_y_spin,
+            connect_value_changed(x),
+            UpdateBeamCentreY(x.get_value())
+        );
+        connect!(
+            relm,
+            propagate_button,
+            connect_toggled(x),
+            SetPropagation(x.get_active())
+        );
+        connect!(
+            relm,
+            propagated_phase_button,
+            connect_toggled(x),
+            SetShowPropagatedPhase(x.get_active())
+        );
+        connect!(
+            relm,
+            focal_length_spin,
+            connect_value_changed(x),
+            UpdateFocalLength(x.get_value())
+        );
+        connect!(
+            relm,
+            distance_scale,
+            connect_value_changed(x),
+            UpdatePropagationDistance(x.get_value())
+        );
+        grid_widget.show_all();
+
+        let mut handler = DrawHandler::new().unwrap();
+        handler.init(&model.area);
+        connect!(relm, model.area, connect_size_allocate(_, _), Paint);
+
+        SimulationController {
+            model,
+            widget: grid_widget,
+            relm: relm.clone(),
+            handler,
+            channel: None,
+        }
+    }
+}
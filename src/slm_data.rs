@@ -1,7 +1,15 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
 
-/// Contains the data for an individual pattern
+use crate::device::DeviceProfile;
+use crate::pattern::{deserialize_patterns, PatternKind};
+use crate::phase_map::PhaseMap;
+use crate::zernike::ZernikeCorrection;
+
+/// Contains the data for an individual vortex pattern
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 pub struct PatternData {
     pub l: i32,
@@ -9,6 +17,270 @@ pub struct PatternData {
     pub k: (f64, f64),
     pub c: (f64, f64),
     pub phase: f64,
+    /// The curvature of a quadratic (Fresnel lens) phase centred on c, in radians per pixel²
+    #[serde(default)]
+    pub lens: f64,
+}
+
+/// Contains the data for an axicon pattern, which makes a Bessel beam of order l
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct AxiconData {
+    pub l: i32,
+    pub a: f64,
+    /// The radial wavenumber of the conical phase, in radians per pixel
+    pub kr: f64,
+    pub k: (f64, f64),
+    pub c: (f64, f64),
+    pub phase: f64,
+}
+
+/// Contains the data for a Laguerre–Gaussian mode LG_pl, with a waist in pixels.
+/// The amplitude of the mode is encoded into the phase, so the mode appears in the
+/// first order of the grating k
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct LaguerreGaussData {
+    pub p: u32,
+    pub l: i32,
+    pub a: f64,
+    pub waist: f64,
+    pub k: (f64, f64),
+    pub c: (f64, f64),
+    pub phase: f64,
+}
+
+/// Contains the data for a Hermite–Gaussian mode HG_mn, with a waist in pixels.
+/// The amplitude of the mode is encoded into the phase, so the mode appears in the
+/// first order of the grating k
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct HermiteGaussData {
+    pub m: u32,
+    pub n: u32,
+    pub a: f64,
+    pub waist: f64,
+    pub k: (f64, f64),
+    pub c: (f64, f64),
+    pub phase: f64,
+}
+
+/// Contains the data for a pattern read from a phase map file (an image or a ```.npy``` array),
+/// such as a hologram designed by the Gerchberg–Saxton algorithm.
+/// The top left of the map is at the container's position
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ImageData {
+    pub path: PathBuf,
+    pub a: f64,
+    /// The level which is a phase of 2π. If it isn't given, it is the whole range of the
+    /// image, or 2π for float arrays
+    #[serde(default)]
+    pub two_pi_level: Option<f64>,
+    /// The phase map, or the error from reading it, which is read from the path the first
+    /// time it is needed
+    #[serde(skip)]
+    pub map: OnceLock<Result<Arc<PhaseMap>, String>>,
+}
+
+impl ImageData {
+    /// An image pattern showing a phase map which has been saved to ```path```
+    pub fn new(path: PathBuf, map: PhaseMap) -> ImageData {
+        ImageData {
+            path,
+            a: 1.0,
+            two_pi_level: None,
+            map: OnceLock::from(Ok(Arc::new(map))),
+        }
+    }
+
+    /// An image pattern showing the phase map in the file at ```path```
+    pub fn open(path: PathBuf, two_pi_level: Option<f64>) -> std::io::Result<ImageData> {
+        let map = PhaseMap::load(&path, two_pi_level)?;
+        Ok(ImageData {
+            two_pi_level,
+            ..ImageData::new(path, map)
+        })
+    }
+
+    /// The phase map, or why it couldn't be read
+    pub fn load(&self) -> Result<&PhaseMap, String> {
+        self.map
+            .get_or_init(|| {
+                PhaseMap::load(&self.path, self.two_pi_level)
+                    .map(Arc::new)
+                    .map_err(|e| format!("couldn't read {}: {}", self.path.display(), e))
+            })
+            .as_ref()
+            .map(|map| map.as_ref())
+            .map_err(|e| e.clone())
+    }
+
+    /// The phase map, or ```None``` if it couldn't be read
+    pub fn map(&self) -> Option<&PhaseMap> {
+        self.load().ok()
+    }
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+/// An optical trap, made by a grating which moves the light to the trap in the Fourier plane,
+/// and a lens which moves it along the beam
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Trap {
+    /// The wavenumber of the grating, in radians per pixel. The trap is 2π/k pixels of a
+    /// device sized far field from the zero order
+    pub k: (f64, f64),
+    /// The curvature of the lens, in radians per pixel², which moves the trap along the beam
+    pub z: f64,
+    /// The relative intensity of the trap
+    pub weight: f64,
+    /// The gain of the trap's grating, which is found by the weighted Gerchberg–Saxton
+    /// algorithm to even out the traps. This is 1 for plain gratings and lenses
+    #[serde(default = "one")]
+    pub gain: f64,
+    /// The phase of the trap's grating
+    #[serde(default)]
+    pub phase: f64,
+}
+
+impl Trap {
+    /// A trap with unit weight, which hasn't been optimised
+    pub fn new(k: (f64, f64)) -> Trap {
+        Trap {
+            k,
+            z: 0.0,
+            weight: 1.0,
+            gain: 1.0,
+            phase: 0.0,
+        }
+    }
+}
+
+/// Contains the data for an array of optical traps.
+/// With 0 iterations the hologram is the superposition of the traps' gratings and lenses,
+/// otherwise the gratings are weighted by the weighted Gerchberg–Saxton algorithm
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct TrapsData {
+    pub traps: Vec<Trap>,
+    pub a: f64,
+    /// The centre of the lenses, which should be the centre of the illuminated area
+    pub c: (f64, f64),
+    pub iterations: usize,
+}
+
+/// How the patterns in a container are combined into the container's field
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CombinationMode {
+    /// The phases of the patterns are added, as if the patterns were placed one after another.
+    /// A pattern's amplitude only turns it on or off: patterns with an amplitude of 0 are
+    /// left out
+    SumPhases,
+    /// The complex fields a·exp(iφ) of the patterns are added
+    #[default]
+    ComplexSuperposition,
+    /// Each pixel shows only one of the patterns, chosen at random.
+    /// The choice is fixed for each pixel, so the mask doesn't change between renders
+    RandomMultiplex,
+}
+
+impl CombinationMode {
+    /// The names of the modes, in the order they are offered in the GUI
+    pub const NAMES: &'static [&'static str] =
+        &["sum_phases", "complex_superposition", "random_multiplex"];
+
+    /// The mode with the given name
+    pub fn from_name(name: &str) -> Option<CombinationMode> {
+        match name {
+            "sum_phases" => Some(CombinationMode::SumPhases),
+            "complex_superposition" => Some(CombinationMode::ComplexSuperposition),
+            "random_multiplex" => Some(CombinationMode::RandomMultiplex),
+            _ => None,
+        }
+    }
+
+    /// The name of the mode
+    pub fn name(self) -> &'static str {
+        match self {
+            CombinationMode::SumPhases => "sum_phases",
+            CombinationMode::ComplexSuperposition => "complex_superposition",
+            CombinationMode::RandomMultiplex => "random_multiplex",
+        }
+    }
+}
+
+/// The beam lighting the region of the SLM a container covers
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Illumination {
+    /// The radius where the intensity of the Gaussian beam falls to 1/e², in pixels
+    pub waist: f64,
+    /// The centre of the beam, in pixels from the container's ```pos```
+    #[serde(default)]
+    pub centre: (f64, f64),
+    /// The wavelength of the beam in nm. Without one, the device's wavelength is used
+    #[serde(default)]
+    pub wavelength: Option<f64>,
+    /// A greyscale image or ```.npy``` array of the measured intensity, which is used instead
+    /// of the Gaussian beam. It is centred on the beam's centre, with one pixel for each pixel
+    /// of the SLM, and its level is proportional to the intensity
+    #[serde(default)]
+    pub intensity_image: Option<PathBuf>,
+    /// The measured intensity scaled so its brightest pixel is 1,
+    /// which is read from ```intensity_image``` the first time it is needed
+    #[serde(skip)]
+    pub intensity: OnceLock<Option<Arc<PhaseMap>>>,
+}
+
+impl Illumination {
+    /// A Gaussian beam with the waist, centred on the container's ```pos```
+    pub fn gaussian(waist: f64) -> Illumination {
+        Illumination {
+            waist,
+            ..Default::default()
+        }
+    }
+
+    /// Use the measured intensity in the file, or the Gaussian beam if there is no file
+    pub fn set_intensity_image(&mut self, path: Option<PathBuf>) {
+        self.intensity_image = path;
+        self.intensity = OnceLock::new();
+    }
+
+    /// The measured intensity, or ```None``` if there isn't one or it couldn't be read
+    pub fn intensity(&self) -> Option<&PhaseMap> {
+        self.intensity
+            .get_or_init(|| {
+                let path = self.intensity_image.as_ref()?;
+                let mut map = PhaseMap::load(path, None).ok()?;
+                let peak = map.phase.iter().cloned().fold(0.0, f64::max);
+                if peak > 0.0 {
+                    for level in map.phase.iter_mut() {
+                        *level = level.max(0.0) / peak;
+                    }
+                }
+                Some(Arc::new(map))
+            })
+            .as_deref()
+    }
+
+    /// The amplitude of the beam at (x, y), measured in pixels from the container's ```pos```.
+    /// This is 1 in the centre of a Gaussian beam, and at the brightest pixel of a measured one
+    pub fn amplitude(&self, x: f64, y: f64) -> f64 {
+        let (dx, dy) = (x - self.centre.0, y - self.centre.1);
+        if let Some(intensity) = self.intensity() {
+            let ix = (dx + intensity.width as f64 / 2.0).floor();
+            let iy = (dy + intensity.height as f64 / 2.0).floor();
+            if ix < 0.0 || iy < 0.0 {
+                return 0.0;
+            }
+            intensity
+                .get(ix as usize, iy as usize)
+                .map_or(0.0, f64::sqrt)
+        } else if self.waist > 0.0 {
+            (-(dx * dx + dy * dy) / (self.waist * self.waist)).exp()
+        } else {
+            0.0
+        }
+    }
 }
 
 /// Contains the important data which is used to create a pattern container
@@ -18,5 +290,126 @@ pub struct PatternContainerData {
     pub bottom_right: (f64, f64),
     pub pos: (f64, f64),
     pub scale: (f64, f64),
-    pub patterns: HashMap<usize, PatternData>,
+    #[serde(deserialize_with = "deserialize_patterns")]
+    pub patterns: HashMap<usize, PatternKind>,
+    /// How the patterns are combined. Older files are complex superpositions
+    #[serde(default)]
+    pub combination: CombinationMode,
+    /// The beam lighting the container. Amplitude encoded patterns are corrected for it,
+    /// and it is used by the simulation
+    #[serde(default)]
+    pub illumination: Option<Illumination>,
+}
+
+impl PatternContainerData {
+    /// An empty container covering the whole device
+    pub fn for_device(device: &DeviceProfile) -> PatternContainerData {
+        PatternContainerData {
+            top_left: (0.0, 0.0),
+            bottom_right: (device.width as f64, device.height as f64),
+            scale: (1.0, 1.0),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the screen pixel (x, y) is inside the container's crop rectangle
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.top_left.0
+            && y >= self.top_left.1
+            && x < self.bottom_right.0
+            && y < self.bottom_right.1
+    }
+
+    /// The fields of the container which can be set by name, as in sweeps
+    pub const PARAMETERS: &'static [&'static str] = &["pos.0", "pos.1", "scale.0", "scale.1"];
+
+    /// Set a field of the container by name. Returns false if it has no such field
+    pub fn set_parameter(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "pos.0" => self.pos.0 = value,
+            "pos.1" => self.pos.1 = value,
+            "scale.0" => self.scale.0 = value,
+            "scale.1" => self.scale.1 = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Everything which is saved to file: the device, the containers displayed on it,
+/// and the aberration correction added over the whole screen
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct SLMData {
+    pub device: DeviceProfile,
+    pub containers: HashMap<usize, PatternContainerData>,
+    #[serde(default)]
+    pub correction: ZernikeCorrection,
+}
+
+impl SLMData {
+    /// Read the data from json.
+    /// Older files which only contain the containers are given the default device
+    /// The device is checked, as in ```DeviceProfile::validate```
+    pub fn from_reader<R: Read>(reader: R) -> serde_json::Result<SLMData> {
+        let value: serde_json::Value = serde_json::de::from_reader(reader)?;
+        let data = if value.get("containers").is_some() {
+            serde_json::from_value::<SLMData>(value)?
+        } else {
+            SLMData {
+                containers: serde_json::from_value(value)?,
+                ..Default::default()
+            }
+        };
+        data.device.validate().map_err(serde::de::Error::custom)?;
+        Ok(data)
+    }
+
+    /// Read the phase maps of the image patterns, giving an error for each one which can't
+    /// be read. Patterns whose maps can't be read have no field
+    pub fn image_errors(&self) -> Vec<String> {
+        let mut ids = self.containers.keys().collect::<Vec<_>>();
+        ids.sort();
+        ids.into_iter()
+            .flat_map(|c_id| {
+                let mut patterns = self.containers[c_id].patterns.iter().collect::<Vec<_>>();
+                patterns.sort_by_key(|(&p_id, _)| p_id);
+                patterns.into_iter().filter_map(move |(p_id, pattern)| match pattern {
+                    PatternKind::Image(image) => image
+                        .load()
+                        .err()
+                        .map(|e| format!("pattern {} of container {}: {}", p_id, c_id, e)),
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    /// Write the data as json
+    pub fn to_writer<W: Write>(&self, writer: W) -> serde_json::Result<()> {
+        serde_json::ser::to_writer_pretty(writer, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreadable_images_are_reported() {
+        let image = ImageData {
+            path: PathBuf::from("/nonexistent/hologram.png"),
+            a: 1.0,
+            ..Default::default()
+        };
+        assert!(image.map().is_none());
+        assert!(image.load().unwrap_err().contains("hologram.png"));
+
+        let mut container = PatternContainerData::for_device(&DeviceProfile::default());
+        container.patterns.insert(2, PatternKind::Image(image));
+        let mut data = SLMData::default();
+        data.containers.insert(1, container);
+        let errors = data.image_errors();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("pattern 2 of container 1"), "{}", errors[0]);
+    }
 }
@@ -0,0 +1,163 @@
+//! This module contains the Zernike polynomial correction for aberrations in the optical system.
+//! The correction is added to the whole screen, on top of the pattern containers
+use serde::{Deserialize, Serialize};
+
+use crate::device::DeviceProfile;
+
+/// The number of polynomials in a correction, which are the Noll indices 1 to 36
+pub const NUM_POLYNOMIALS: usize = 36;
+
+/// Convert a Noll index (starting from 1) into the radial order n and azimuthal frequency m.
+/// Negative m are the sine polynomials, and positive m are the cosine polynomials
+pub fn noll_to_nm(j: usize) -> (u32, i32) {
+    let j = j.max(1);
+    let n = ((((8 * (j - 1) + 1) as f64).sqrt() - 1.0) / 2.0) as u32;
+    let p = j as i32 - (n * (n + 1) / 2) as i32;
+    let k = (n % 2) as i32;
+    let m = (p + k) / 2 * 2 - k;
+    if m != 0 && j % 2 == 1 {
+        (n, -m)
+    } else {
+        (n, m)
+    }
+}
+
+/// The common name of the polynomial at the Noll index j, if it has one
+pub fn name(j: usize) -> Option<&'static str> {
+    match j {
+        1 => Some("piston"),
+        2 => Some("tilt x"),
+        3 => Some("tilt y"),
+        4 => Some("defocus"),
+        5 => Some("oblique astigmatism"),
+        6 => Some("vertical astigmatism"),
+        7 => Some("vertical coma"),
+        8 => Some("horizontal coma"),
+        9 => Some("vertical trefoil"),
+        10 => Some("oblique trefoil"),
+        11 => Some("spherical"),
+        _ => None,
+    }
+}
+
+fn factorial(n: u32) -> f64 {
+    (1..=n).map(f64::from).product()
+}
+
+/// The radial part of the Zernike polynomial
+fn radial(n: u32, m: u32, rho: f64) -> f64 {
+    (0..=(n - m) / 2)
+        .map(|k| {
+            let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+            sign * factorial(n - k)
+                / (factorial(k) * factorial((n + m) / 2 - k) * factorial((n - m) / 2 - k))
+                * rho.powi((n - 2 * k) as i32)
+        })
+        .sum()
+}
+
+/// The Zernike polynomial at the Noll index j, normalised to have an rms of 1 over the unit disc.
+/// (rho, theta) are polar coordinates in the unit disc
+pub fn zernike(j: usize, rho: f64, theta: f64) -> f64 {
+    let (n, m) = noll_to_nm(j);
+    let abs_m = m.unsigned_abs();
+    let r = radial(n, abs_m, rho);
+    if m == 0 {
+        f64::from(n + 1).sqrt() * r
+    } else if m > 0 {
+        (2.0 * f64::from(n + 1)).sqrt() * r * (f64::from(abs_m) * theta).cos()
+    } else {
+        (2.0 * f64::from(n + 1)).sqrt() * r * (f64::from(abs_m) * theta).sin()
+    }
+}
+
+/// A correction made of Zernike polynomials over a circular pupil
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ZernikeCorrection {
+    /// The centre of the pupil, in pixels
+    pub centre: (f64, f64),
+    /// The radius of the pupil, in pixels
+    pub radius: f64,
+    /// The coefficient of each polynomial in rms radians, in order of Noll index starting at 1
+    pub coefficients: Vec<f64>,
+}
+
+impl Default for ZernikeCorrection {
+    fn default() -> Self {
+        ZernikeCorrection::for_device(&DeviceProfile::default())
+    }
+}
+
+impl ZernikeCorrection {
+    /// A correction with no aberrations, with the pupil filling the middle of the device
+    pub fn for_device(device: &DeviceProfile) -> Self {
+        ZernikeCorrection {
+            centre: (device.width as f64 / 2.0, device.height as f64 / 2.0),
+            radius: device.width.min(device.height) as f64 / 2.0,
+            coefficients: vec![0.0; NUM_POLYNOMIALS],
+        }
+    }
+
+    /// The phase of the correction at the screen pixel (x, y).
+    /// The correction is zero outside of the pupil
+    pub fn phase(&self, x: f64, y: f64) -> f64 {
+        if self.radius <= 0.0 {
+            return 0.0;
+        }
+        let (dx, dy) = (x - self.centre.0, y - self.centre.1);
+        let rho = (dx * dx + dy * dy).sqrt() / self.radius;
+        if rho > 1.0 {
+            return 0.0;
+        }
+        let theta = dy.atan2(dx);
+        self.coefficients
+            .iter()
+            .enumerate()
+            .filter(|(_, &c)| c != 0.0)
+            .map(|(i, c)| c * zernike(i + 1, rho, theta))
+            .sum()
+    }
+
+    /// Whether every coefficient is zero, so the correction does nothing
+    pub fn is_zero(&self) -> bool {
+        self.coefficients.iter().all(|&c| c == 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noll_indices_match_the_standard_table() {
+        let table = [
+            (0, 0),
+            (1, 1),
+            (1, -1),
+            (2, 0),
+            (2, -2),
+            (2, 2),
+            (3, -1),
+            (3, 1),
+            (3, -3),
+            (3, 3),
+            (4, 0),
+            (4, 2),
+            (4, -2),
+            (4, 4),
+            (4, -4),
+        ];
+        for (i, &nm) in table.iter().enumerate() {
+            assert_eq!(noll_to_nm(i + 1), nm, "Noll index {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn correction_is_zero_outside_the_pupil() {
+        let mut correction = ZernikeCorrection::for_device(&DeviceProfile::default());
+        correction.coefficients[3] = 1.0;
+        let (cx, cy) = correction.centre;
+        assert_eq!(correction.phase(cx + correction.radius + 1.0, cy), 0.0);
+        assert!((correction.phase(cx, cy) + 3f64.sqrt()).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,175 @@
+//! This file contains the controls for the aberration correction added to the whole screen
+
+use self::ZernikeControllerMsg::*;
+use gtk::{EntryExt, GridExt, LabelExt, SpinButtonExt, SpinButtonSignals, WidgetExt};
+use relm::{Relm, Update, Widget};
+
+use crate::gui::{SLMController, SLMControllerMsg};
+use phase::zernike::{self, ZernikeCorrection};
+
+pub struct ZernikeControllerModel {
+    correction: ZernikeCorrection,
+    parent_relm: Relm<SLMController>,
+}
+
+#[derive(Msg)]
+pub enum ZernikeControllerMsg {
+    UpdateCentreX(f64),
+    UpdateCentreY(f64),
+    UpdateRadius(f64),
+    UpdateCoefficient(usize, f64),
+}
+
+pub struct ZernikeController {
+    pub model: ZernikeControllerModel,
+    pub widget: gtk::Grid,
+}
+
+impl Update for ZernikeController {
+    type Model = ZernikeControllerModel;
+    type ModelParam = (ZernikeCorrection, Relm<SLMController>);
+    type Msg = ZernikeControllerMsg;
+
+    fn model(_: &Relm<Self>, param: Self::ModelParam) -> Self::Model {
+        ZernikeControllerModel {
+            correction: param.0,
+            parent_relm: param.1,
+        }
+    }
+
+    fn update(&mut self, event: Self::Msg) {
+        match event {
+            UpdateCentreX(x) => self
+                .model
+                .parent_relm
+                .stream()
+                .emit(SLMControllerMsg::UpdateZernikeCentreX(x)),
+            UpdateCentreY(x) => self
+                .model
+                .parent_relm
+                .stream()
+                .emit(SLMControllerMsg::UpdateZernikeCentreY(x)),
+            UpdateRadius(x) => self
+                .model
+                .parent_relm
+                .stream()
+                .emit(SLMControllerMsg::UpdateZernikeRadius(x)),
+            UpdateCoefficient(i, x) => self
+                .model
+                .parent_relm
+                .stream()
+                .emit(SLMControllerMsg::UpdateZernikeCoefficient(i, x)),
+        }
+    }
+}
+
+impl Widget for ZernikeController {
+    type Root = gtk::Grid;
+
+    fn root(&self) -> Self::Root {
+        self.widget.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+        let spinner_char_width = 7;
+        let coefficient_columns = 3;
+        let grid_widget = gtk::Grid::new();
+        grid_widget.set_column_spacing(5);
+
+        let cx_spin_adjustment = gtk::Adjustment::new(
+            model.correction.centre.0,
+            f64::MIN,
+            f64::MAX,
+            1.0,
+            0.0,
+            0.0,
+        );
+        let cy_spin_adjustment = gtk::Adjustment::new(
+            model.correction.centre.1,
+            f64::MIN,
+            f64::MAX,
+            1.0,
+            0.0,
+            0.0,
+        );
+        let radius_spin_adjustment = gtk::Adjustment::new(
+            model.correction.radius,
+            0.0,
+            f64::MAX,
+            1.0,
+            0.0,
+            0.0,
+        );
+        let centre_label = gtk::Label::new("pupil centre (x, y)");
+        let cx_spinner = gtk::SpinButton::new(&cx_spin_adjustment, 0.0, 3);
+        cx_spinner.set_width_chars(spinner_char_width);
+        let cy_spinner = gtk::SpinButton::new(&cy_spin_adjustment, 0.0, 3);
+        cy_spinner.set_width_chars(spinner_char_width);
+        let radius_label = gtk::Label::new("pupil radius");
+        let radius_spinner = gtk::SpinButton::new(&radius_spin_adjustment, 0.0, 3);
+        radius_spinner.set_width_chars(spinner_char_width);
+
+        grid_widget.attach(&centre_label, 0, 0, 1, 1);
+        grid_widget.attach(&cx_spinner, 1, 0, 1, 1);
+        grid_widget.attach(&cy_spinner, 2, 0, 1, 1);
+        grid_widget.attach(&radius_label, 3, 0, 1, 1);
+        grid_widget.attach(&radius_spinner, 4, 0, 1, 1);
+
+        connect!(
+            relm,
+            cx_spinner,
+            connect_value_changed(x),
+            UpdateCentreX(x.get_value())
+        );
+        connect!(
+            relm,
+            cy_spinner,
+            connect_value_changed(x),
+            UpdateCentreY(x.get_value())
+        );
+        connect!(
+            relm,
+            radius_spinner,
+            connect_value_changed(x),
+            UpdateRadius(x.get_value())
+        );
+
+        for i in 0..zernike::NUM_POLYNOMIALS {
+            let j = i + 1;
+            let (n, m) = zernike::noll_to_nm(j);
+            let label = match zernike::name(j) {
+                Some(name) => format!("Z{} {}", j, name),
+                None => format!("Z{} (n={}, m={})", j, n, m),
+            };
+            let adjustment = gtk::Adjustment::new(
+                model.correction.coefficients.get(i).cloned().unwrap_or(0.0),
+                f64::MIN,
+                f64::MAX,
+                0.1,
+                0.0,
+                0.0,
+            );
+            let coefficient_label = gtk::Label::new(label.as_str());
+            coefficient_label.set_xalign(0.0);
+            let coefficient_spinner = gtk::SpinButton::new(&adjustment, 0.0, 3);
+            coefficient_spinner.set_width_chars(spinner_char_width);
+            let row = 1 + (i / coefficient_columns) as i32;
+            let column = 2 * (i % coefficient_columns) as i32;
+            grid_widget.attach(&coefficient_label, column, row, 1, 1);
+            grid_widget.attach(&coefficient_spinner, column + 1, row, 1, 1);
+            connect!(
+                relm,
+                coefficient_spinner,
+                connect_value_changed(x),
+                UpdateCoefficient(i, x.get_value())
+            );
+        }
+
+        grid_widget.show_all();
+
+        ZernikeController {
+            model,
+            widget: grid_widget,
+        }
+    }
+}